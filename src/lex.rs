@@ -1,5 +1,109 @@
 use crate::model::Token;
 use crate::model::TokenType::*;
+use std::iter::Peekable;
+use std::str::Chars;
+
+fn is_ident_char(ch: char) -> bool {
+    ch.is_alphanumeric() || "-#'?=></".contains(ch)
+}
+
+fn is_digits(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_digit())
+}
+
+// digits in base 10, or after a 0x, 0o or 0b prefix, with single underscores
+// allowed between digits
+fn is_integer(s: &str) -> bool {
+    let (radix, digits) = split_radix(s);
+    !digits.is_empty()
+        && !digits.starts_with('_')
+        && !digits.ends_with('_')
+        && !digits.contains("__")
+        && digits.chars().all(|c| c == '_' || c.is_digit(radix))
+}
+
+/// Splits an unsigned integer literal into its radix and digits.
+pub fn split_radix(s: &str) -> (u32, &str) {
+    match s.get(..2) {
+        Some("0x") | Some("0X") => (16, &s[2..]),
+        Some("0o") | Some("0O") => (8, &s[2..]),
+        Some("0b") | Some("0B") => (2, &s[2..]),
+        _ => (10, s),
+    }
+}
+
+// [+-]digits(.digits*)?([eE][+-]?digits)?
+fn is_double(s: &str) -> bool {
+    let (mantissa, exponent) = match s.find(['e', 'E']) {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+    let mantissa_ok = match mantissa.find('.') {
+        Some(i) => {
+            let frac = &mantissa[i + 1..];
+            is_digits(&mantissa[..i]) && frac.chars().all(|c| c.is_ascii_digit())
+        }
+        None => is_digits(mantissa),
+    };
+    let exponent_ok = match exponent {
+        Some(e) => is_digits(e.strip_prefix(['+', '-']).unwrap_or(e)),
+        None => true,
+    };
+    mantissa_ok && exponent_ok
+}
+
+/// Lexes a numeric literal whose first character (a digit or sign) has
+/// already been consumed.
+fn lex_number(
+    first: char,
+    chars: &mut Peekable<Chars>,
+    line_number: usize,
+) -> Result<Token, String> {
+    let mut text = first.to_string();
+    while let Some(&next) = chars.peek() {
+        // in hex literals e is a digit rather than an exponent
+        let after_exponent = text.ends_with(['e', 'E']) && !text.contains(['x', 'X']);
+        if next.is_alphanumeric()
+            || next == '.'
+            || next == '_'
+            || (after_exponent && (next == '+' || next == '-'))
+        {
+            text.push(next);
+            chars.next();
+        } else {
+            break;
+        }
+    }
+
+    let unsigned = text.strip_prefix(['+', '-']).unwrap_or(&text);
+    let _type = if let Some(digits) = unsigned.strip_suffix("u8") {
+        // a u8 suffix makes an integer literal a byte
+        if !is_integer(digits) {
+            return Err(format!(
+                "malformed byte literal {} on line {}",
+                text, line_number
+            ));
+        }
+        text.truncate(text.len() - 2);
+        ByteLiteral
+    } else if is_integer(unsigned) {
+        IntLiteral
+    } else if is_double(unsigned) {
+        DoubleLiteral
+    } else {
+        return Err(format!(
+            "malformed number literal {} on line {}",
+            text, line_number
+        ));
+    };
+
+    text.retain(|c| c != '_');
+    Ok(Token {
+        _type,
+        text,
+        line_number,
+    })
+}
 
 pub fn lex(s: String) -> Result<Vec<Token>, String> {
     let mut tokens: Vec<Token> = vec![];
@@ -18,7 +122,7 @@ pub fn lex(s: String) -> Result<Vec<Token>, String> {
             '\t' | ' ' => (),
             '\n' => line_number += 1,
             ';' => {
-                while let Some(next) = chars.next() {
+                for next in chars.by_ref() {
                     if next == '\n' {
                         line_number += 1;
                         break;
@@ -46,23 +150,70 @@ pub fn lex(s: String) -> Result<Vec<Token>, String> {
                 text: ch.to_string(),
                 line_number,
             }),
+            '{' => tokens.push(Token {
+                _type: BraceL,
+                text: ch.to_string(),
+                line_number,
+            }),
+            '}' => tokens.push(Token {
+                _type: BraceR,
+                text: ch.to_string(),
+                line_number,
+            }),
+            '#' => match chars.next() {
+                Some('{') => tokens.push(Token {
+                    _type: HashBraceL,
+                    text: "#{".to_string(),
+                    line_number,
+                }),
+                Some('(') => tokens.push(Token {
+                    _type: HashParenL,
+                    text: "#(".to_string(),
+                    line_number,
+                }),
+                next => {
+                    return Err(format!(
+                        "unrecognized dispatch #{} on line {}",
+                        next.map(String::from).unwrap_or_default(),
+                        line_number
+                    ));
+                }
+            },
             '\'' => tokens.push(Token {
                 _type: Quote,
                 text: ch.to_string(),
                 line_number,
             }),
+            '`' => tokens.push(Token {
+                _type: Quasiquote,
+                text: ch.to_string(),
+                line_number,
+            }),
+            '~' => {
+                if chars.peek() == Some(&'@') {
+                    chars.next();
+                    tokens.push(Token {
+                        _type: UnquoteSplicing,
+                        text: "~@".to_string(),
+                        line_number,
+                    });
+                } else {
+                    tokens.push(Token {
+                        _type: Unquote,
+                        text: ch.to_string(),
+                        line_number,
+                    });
+                }
+            }
+
+            '+' | '-' if chars.peek().is_some_and(|c| c.is_ascii_digit()) => {
+                tokens.push(lex_number(ch, &mut chars, line_number)?);
+            }
 
-            'a'..='z' | 'A'..='Z' | '+' | '-' | '*' | '/' | '=' | '?' | '<' | '>' => {
+            'a'..='z' | 'A'..='Z' | '+' | '-' | '*' | '/' | '=' | '?' | '<' | '>' | '&' => {
                 let mut text = ch.to_string();
                 while let Some(next) = chars.peek() {
-                    if next.is_alphanumeric()
-                        || *next == '-'
-                        || *next == '\''
-                        || *next == '?'
-                        || *next == '='
-                        || *next == '>'
-                        || *next == '<'
-                    {
+                    if is_ident_char(*next) {
                         text.push(*next);
                         chars.next();
                     } else {
@@ -76,6 +227,45 @@ pub fn lex(s: String) -> Result<Vec<Token>, String> {
                 });
             }
 
+            // %, %1, %2... and %& name the parameters of a #() lambda; any other
+            // identifier starting with % is an ordinary symbol
+            '%' => {
+                let mut text = ch.to_string();
+                while let Some(next) = chars.peek() {
+                    if is_ident_char(*next) || *next == '&' {
+                        text.push(*next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token {
+                    _type: Ident,
+                    text,
+                    line_number,
+                });
+            }
+
+            ':' => {
+                let mut text = String::new();
+                while let Some(next) = chars.peek() {
+                    if is_ident_char(*next) {
+                        text.push(*next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                if text.is_empty() {
+                    return Err(format!("empty keyword on line {}", line_number));
+                }
+                tokens.push(Token {
+                    _type: KeywordLiteral,
+                    text,
+                    line_number,
+                });
+            }
+
             '\"' => {
                 let mut text = String::new();
                 while let Some(next) = chars.peek() {
@@ -95,7 +285,7 @@ pub fn lex(s: String) -> Result<Vec<Token>, String> {
                         }
                         continue;
                     }
-                    text.push(next.clone());
+                    text.push(*next);
                     chars.next();
                 }
                 tokens.push(Token {
@@ -105,33 +295,57 @@ pub fn lex(s: String) -> Result<Vec<Token>, String> {
                 });
             }
 
-            '0'..='9' => {
-                let mut text = ch.to_string();
-                let mut is_double = false;
+            '0'..='9' => tokens.push(lex_number(ch, &mut chars, line_number)?),
 
-                while let Some(next) = chars.peek() {
-                    if *next == '.' {
-                        if is_double {
-                            return Err(format!(
-                                "multiple decimal places in float literal on line {}",
-                                line_number
-                            ));
-                        }
-                        is_double = true;
-                        text.push(next.clone());
-                        chars.next();
-                        continue;
+            '\\' => {
+                let first = match chars.next() {
+                    Some(c) => c,
+                    None => {
+                        return Err(format!(
+                            "character literal missing its character on line {}",
+                            line_number
+                        ))
                     }
-                    if next.is_numeric() {
-                        text.push(next.clone());
-                        chars.next();
-                    } else {
-                        break;
+                };
+
+                // \a is a single character, while \newline or \u03BB name one
+                let mut name = first.to_string();
+                if first.is_alphabetic() {
+                    while let Some(next) = chars.peek() {
+                        if next.is_alphanumeric() {
+                            name.push(*next);
+                            chars.next();
+                        } else {
+                            break;
+                        }
                     }
                 }
+
+                let ch = match &name[..] {
+                    _ if name.chars().count() == 1 => first,
+                    "newline" => '\n',
+                    "space" => ' ',
+                    "tab" => '\t',
+                    "return" => '\r',
+                    _ if name.starts_with('u') => u32::from_str_radix(&name[1..], 16)
+                        .ok()
+                        .and_then(std::char::from_u32)
+                        .ok_or_else(|| {
+                            format!(
+                                "bad unicode character literal \\{} on line {}",
+                                name, line_number
+                            )
+                        })?,
+                    _ => {
+                        return Err(format!(
+                            "unknown character literal \\{} on line {}",
+                            name, line_number
+                        ))
+                    }
+                };
                 tokens.push(Token {
-                    _type: if is_double { DoubleLiteral } else { IntLiteral },
-                    text,
+                    _type: CharLiteral,
+                    text: ch.to_string(),
                     line_number,
                 });
             }
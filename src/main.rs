@@ -2,12 +2,23 @@ mod lex;
 mod model;
 mod parse;
 mod runtime;
+#[cfg(test)]
+mod tests;
 
 const PRELUDE: &str = include_str!("../prelude.tau");
 
+/// Lexes, parses and executes the script at `path`, reporting why it could not be run.
+fn run_script(runtime: &mut model::Runtime, path: &str) -> Result<(), String> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    let tokens = lex::lex(contents).map_err(|s| format!("Lexer error in {}: {}", path, s))?;
+    let ast = parse::parse(&tokens).map_err(|s| format!("Parse error in {}: {}", path, s))?;
+    runtime::execute(runtime, ast);
+    Ok(())
+}
+
 fn main() {
     use std::env;
-    use std::fs::File;
     use std::io;
     use std::io::prelude::*;
 
@@ -46,9 +57,9 @@ fn main() {
                 }
             };
 
-            let scope = runtime.root_scope.clone();
+            let scope = std::sync::Arc::new(model::Scope::default());
             for node in ast {
-                match runtime::eval(&node, &mut runtime, &scope) {
+                match runtime::eval_toplevel(&node, &mut runtime, &scope) {
                     Ok(elt) => println!("{}", runtime::format_elt(&elt)),
                     Err(e) => println!("error: {:#?}", e),
                 }
@@ -57,17 +68,8 @@ fn main() {
     } else {
         args.next();
         for arg in args {
-            let mut file = File::open(&arg).unwrap();
-            let mut contents = String::new();
-            file.read_to_string(&mut contents).unwrap();
-
-            let tokens = lex::lex(contents);
-            match tokens {
-                Ok(tokens) => {
-                    let ast = parse::parse(&tokens);
-                    runtime::execute(&mut runtime, ast.unwrap());
-                }
-                Err(s) => println!("Lexer error in {}: {}", arg, s),
+            if let Err(e) = run_script(&mut runtime, &arg) {
+                println!("{}", e);
             }
         }
     }
@@ -1,4 +1,9 @@
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::sync::Arc;
 
 #[derive(Debug, PartialEq)]
 pub enum TokenType {
@@ -6,11 +11,21 @@ pub enum TokenType {
     ParenR,
     BracketL,
     BracketR,
+    BraceL,
+    BraceR,
+    HashBraceL,
+    HashParenL,
     Ident,
+    KeywordLiteral,
     IntLiteral,
     StringLiteral,
+    CharLiteral,
+    ByteLiteral,
     DoubleLiteral,
     Quote,
+    Quasiquote,
+    Unquote,
+    UnquoteSplicing,
 }
 
 #[derive(Debug, PartialEq)]
@@ -20,18 +35,76 @@ pub struct Token {
     pub line_number: usize,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Builtin {
     Print,
     Println,
     Def,
     Quote,
+    Quasiquote,
+    Unquote,
+    UnquoteSplicing,
     Fn_,
+    Let,
+    Do,
+    MacroExpand1,
+    MacroExpand,
+    Gensym,
+    HashMap_,
+    Get,
+    Assoc,
+    Dissoc,
+    Keys,
+    Vals,
+    Contains_,
+    HashSet_,
+    Conj,
+    Disj,
+    Union,
+    Intersection,
+    Difference,
+    Count,
+    Str,
+    StrCat,
+    StrSubstr,
+    StrContains,
+    StrSplit,
+    StrJoin,
+    StrTrim,
+    StrUpper,
+    StrLower,
+    StrStartsWith,
+    StrEndsWith,
+    StrReplace,
+    StrIndexOf,
+    IoSlurp,
+    IoSpit,
+    IoAppend,
+    IoLines,
+    IoListDir,
+    IoExists,
+    IoDelete,
+    ParallelMap,
+    ParallelFilter,
+    Numerator,
+    Denominator,
+    Quot,
+    Rem,
+    Mod,
+    Char,
+    Byte,
+    Int,
     Macro,
     Car,
     Cdr,
     Cons,
     Empty_,
+    Map,
+    Filter,
+    Foldl,
+    Reverse,
+    Range,
+    List_,
     If,
     Not,
     Nth,
@@ -39,39 +112,182 @@ pub enum Builtin {
     Minus,
     Mult,
     Div,
+    NumEq,
+    LT,
     GT,
+    LTEq,
+    GTEq,
+    Compare,
     Equal,
     Assert,
     AssertEq,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, Clone)]
 pub enum Elt {
     Int(i64),
+    /// An integer outside the range of `i64`. Arithmetic demotes results that fit back to `Int`,
+    /// so the same number is never represented both ways.
+    BigInt(BigInt),
+    /// A fraction in lowest terms whose denominator is not 1; whole results are demoted to
+    /// `Int` or `BigInt`.
+    Ratio(BigRational),
     Double(f64),
     Bool(bool),
     String_(String),
+    Char(char),
+    Byte(u8),
     Symbol(String),
+    Keyword(String),
     List(Vec<Elt>),
     Vector(Vec<Elt>),
+    Map(EltMap),
+    Set(EltSet),
+    // clauses are shared so that looking a function up does not copy its body
     Function {
-        lexical_bindings: Vec<String>,
-        body: Box<Elt>,
+        clauses: Arc<[Clause]>,
+        env: Arc<Scope>,
     },
     BuiltinFunction(Builtin),
     Macro {
-        lexical_bindings: Vec<String>,
-        body: Box<Elt>,
+        clauses: Arc<[Clause]>,
+        env: Arc<Scope>,
     },
     Nil,
 }
 
+/// The contents of an immutable hash-map value. The map is persistent, so `assoc` and `dissoc`
+/// share structure with the original rather than copying it. The hasher is unseeded so that
+/// iteration order, and therefore printing, is the same from run to run.
+pub type EltMap = im::HashMap<Elt, Elt, BuildHasherDefault<DefaultHasher>>;
+
+/// The contents of an immutable set value, persistent and hashed like `EltMap`.
+pub type EltSet = im::HashSet<Elt, BuildHasherDefault<DefaultHasher>>;
+
+/// Hashes the entries of an unordered collection so that the result does not depend on order.
+fn hash_unordered<T: Hash>(entries: impl Iterator<Item = T>) -> u64 {
+    let mut combined = 0u64;
+    for entry in entries {
+        let mut hasher = DefaultHasher::new();
+        entry.hash(&mut hasher);
+        combined = combined.wrapping_add(hasher.finish());
+    }
+    combined
+}
+
+// Elements are compared structurally, so they can be used as keys. Unlike `==`, NaN equals
+// itself here, which keeps the comparison reflexive.
+impl PartialEq for Elt {
+    fn eq(&self, other: &Elt) -> bool {
+        match (self, other) {
+            (Elt::Int(a), Elt::Int(b)) => a == b,
+            (Elt::BigInt(a), Elt::BigInt(b)) => a == b,
+            (Elt::Ratio(a), Elt::Ratio(b)) => a == b,
+            (Elt::Double(a), Elt::Double(b)) => a == b || (a.is_nan() && b.is_nan()),
+            (Elt::Bool(a), Elt::Bool(b)) => a == b,
+            (Elt::String_(a), Elt::String_(b)) => a == b,
+            (Elt::Char(a), Elt::Char(b)) => a == b,
+            (Elt::Byte(a), Elt::Byte(b)) => a == b,
+            (Elt::Symbol(a), Elt::Symbol(b)) => a == b,
+            (Elt::Keyword(a), Elt::Keyword(b)) => a == b,
+            (Elt::List(a), Elt::List(b)) => a == b,
+            (Elt::Vector(a), Elt::Vector(b)) => a == b,
+            (Elt::Map(a), Elt::Map(b)) => a == b,
+            (Elt::Set(a), Elt::Set(b)) => a == b,
+            (
+                Elt::Function {
+                    clauses: a,
+                    env: a_env,
+                },
+                Elt::Function {
+                    clauses: b,
+                    env: b_env,
+                },
+            )
+            | (
+                Elt::Macro {
+                    clauses: a,
+                    env: a_env,
+                },
+                Elt::Macro {
+                    clauses: b,
+                    env: b_env,
+                },
+            ) => a == b && a_env == b_env,
+            (Elt::BuiltinFunction(a), Elt::BuiltinFunction(b)) => a == b,
+            (Elt::Nil, Elt::Nil) => true,
+            _ => false,
+        }
+    }
+}
+
+// Functions and macros hash only by variant; equal ones still hash equally.
+impl Eq for Elt {}
+
+impl Hash for Elt {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Elt::Int(i) => i.hash(state),
+            Elt::BigInt(b) => b.hash(state),
+            Elt::Ratio(r) => r.hash(state),
+            // 0.0 and -0.0 compare equal, as do all NaNs, so they must hash the same
+            Elt::Double(d) => {
+                let d = if *d == 0.0 {
+                    0.0
+                } else if d.is_nan() {
+                    f64::NAN
+                } else {
+                    *d
+                };
+                d.to_bits().hash(state)
+            }
+            Elt::Bool(b) => b.hash(state),
+            Elt::Char(c) => c.hash(state),
+            Elt::Byte(b) => b.hash(state),
+            Elt::String_(s) | Elt::Symbol(s) | Elt::Keyword(s) => s.hash(state),
+            Elt::List(elts) | Elt::Vector(elts) => elts.hash(state),
+            Elt::Map(map) => hash_unordered(map.iter()).hash(state),
+            Elt::Set(set) => hash_unordered(set.iter()).hash(state),
+            Elt::BuiltinFunction(b) => b.hash(state),
+            Elt::Function { .. } | Elt::Macro { .. } | Elt::Nil => (),
+        }
+    }
+}
+
+/// The target of a binding: either a plain name, or a vector pattern such as `[a [b c] & more]`
+/// that takes a list or vector apart positionally.
 #[derive(Debug, PartialEq, Clone)]
+pub enum Pattern {
+    Symbol(String),
+    Sequence {
+        items: Vec<Pattern>,
+        rest: Option<Box<Pattern>>,
+    },
+}
+
+/// One parameter list and body of a function or macro. Functions may have several clauses, one
+/// per accepted arity.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Clause {
+    pub lexical_bindings: Vec<Pattern>,
+    pub rest_binding: Option<Box<Pattern>>,
+    pub body: Arc<Elt>,
+}
+
+/// A frame of local bindings. Frames are chained through `parent`, so entering a function only
+/// allocates its own parameters. Globals live in `Runtime::root_scope` and are consulted when a
+/// name is not bound in any frame.
+#[derive(Debug, PartialEq, Clone, Default)]
 pub struct Scope {
     pub bindings: HashMap<String, Elt>,
+    pub parent: Option<Arc<Scope>>,
 }
 
+/// Global state. The root scope is shared with parallel workers, which may read globals but
+/// not define them.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Runtime {
-    pub root_scope: Scope,
+    pub root_scope: Arc<Scope>,
+    pub in_worker: bool,
 }
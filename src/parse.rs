@@ -1,6 +1,29 @@
+use crate::lex::split_radix;
 use crate::model::Elt::*;
 use crate::model::TokenType::*;
 use crate::model::*;
+use std::convert::TryFrom;
+use std::num::{IntErrorKind, ParseIntError};
+
+/// Splits an integer literal into its sign-prefixed digits and radix.
+fn split_int(text: &str) -> (String, u32) {
+    let (sign, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (radix, digits) = split_radix(unsigned);
+    (format!("{}{}", sign, digits), radix)
+}
+
+fn parse_int(text: &str) -> Result<i64, ParseIntError> {
+    let (digits, radix) = split_int(text);
+    i64::from_str_radix(&digits, radix)
+}
+
+fn parse_big_int(text: &str) -> Option<num_bigint::BigInt> {
+    let (digits, radix) = split_int(text);
+    num_bigint::BigInt::parse_bytes(digits.as_bytes(), radix)
+}
 
 fn parse_expr(tokens: &[Token], index: usize) -> Result<(Elt, usize), String> {
     match tokens.get(index) {
@@ -8,29 +31,63 @@ fn parse_expr(tokens: &[Token], index: usize) -> Result<(Elt, usize), String> {
         Some(Token {
             _type: BracketL, ..
         }) => parse_vector(tokens, index),
+        Some(Token { _type: BraceL, .. }) => parse_map(tokens, index),
+        Some(Token {
+            _type: HashBraceL, ..
+        }) => parse_set(tokens, index),
+        Some(Token {
+            _type: HashParenL, ..
+        }) => parse_lambda(tokens, index),
         Some(Token {
             _type: IntLiteral,
             text,
             line_number,
-        }) => match text.parse::<i64>() {
+        }) => match parse_int(text) {
             Ok(i) => Ok((Int(i), index + 1)),
-            Err(e) => Err(format!(
-                "bad integer literal on line {}: {:?}: {}",
-                line_number, text, e
-            )),
+            // literals too big for an Int are read as BigInts
+            Err(e) => match (e.kind(), parse_big_int(text)) {
+                (IntErrorKind::PosOverflow | IntErrorKind::NegOverflow, Some(n)) => {
+                    Ok((BigInt(n), index + 1))
+                }
+                _ => Err(format!(
+                    "bad integer literal on line {}: {:?}: {}",
+                    line_number, text, e
+                )),
+            },
         },
-        Some(Token { _type: Quote, .. }) => {
-            let (quoted, new_index) = parse_expr(tokens, index + 1)?;
-            Ok((
-                List(vec![BuiltinFunction(Builtin::Quote), quoted]),
-                new_index,
-            ))
-        }
+        Some(Token { _type: Quote, .. }) => parse_prefixed(tokens, index, Builtin::Quote),
+        Some(Token {
+            _type: Quasiquote, ..
+        }) => parse_prefixed(tokens, index, Builtin::Quasiquote),
+        Some(Token { _type: Unquote, .. }) => parse_prefixed(tokens, index, Builtin::Unquote),
+        Some(Token {
+            _type: UnquoteSplicing,
+            ..
+        }) => parse_prefixed(tokens, index, Builtin::UnquoteSplicing),
         Some(Token {
             _type: StringLiteral,
             text,
             ..
         }) => Ok((String_(text.clone()), index + 1)),
+        Some(Token {
+            _type: CharLiteral,
+            text,
+            line_number,
+        }) => match text.chars().next() {
+            Some(c) => Ok((Char(c), index + 1)),
+            None => Err(format!("empty character literal on line {}", line_number)),
+        },
+        Some(Token {
+            _type: ByteLiteral,
+            text,
+            line_number,
+        }) => match parse_int(text).ok().and_then(|i| u8::try_from(i).ok()) {
+            Some(b) => Ok((Byte(b), index + 1)),
+            None => Err(format!(
+                "byte literal {}u8 on line {} is outside 0-255",
+                text, line_number
+            )),
+        },
         Some(Token {
             _type: DoubleLiteral,
             text,
@@ -42,6 +99,11 @@ fn parse_expr(tokens: &[Token], index: usize) -> Result<(Elt, usize), String> {
                 line_number, text, e
             )),
         },
+        Some(Token {
+            _type: KeywordLiteral,
+            text,
+            ..
+        }) => Ok((Elt::Keyword(text.clone()), index + 1)),
         Some(Token {
             _type: Ident, text, ..
         }) => match &text[..] {
@@ -63,10 +125,25 @@ fn parse_expr(tokens: &[Token], index: usize) -> Result<(Elt, usize), String> {
             line_number,
             ..
         }) => Err(format!("unexpected closing paren on line {}", line_number)),
+        Some(Token {
+            _type: BraceR,
+            line_number,
+            ..
+        }) => Err(format!("unexpected closing brace on line {}", line_number)),
         None => Err("unexpected EOF".to_string()),
     }
 }
 
+/// Parses reader shorthand like `'x` or `~x` into the list `(<builtin> x)`.
+fn parse_prefixed(
+    tokens: &[Token],
+    index: usize,
+    builtin: Builtin,
+) -> Result<(Elt, usize), String> {
+    let (quoted, new_index) = parse_expr(tokens, index + 1)?;
+    Ok((List(vec![BuiltinFunction(builtin), quoted]), new_index))
+}
+
 fn parse_vector(tokens: &[Token], index: usize) -> Result<(Elt, usize), String> {
     let mut elts = vec![];
     assert_eq!(BracketL, tokens[index]._type);
@@ -92,9 +169,155 @@ fn parse_vector(tokens: &[Token], index: usize) -> Result<(Elt, usize), String>
     }
 }
 
+/// Parses the forms up to the closing brace of a map or set literal.
+fn parse_braced(tokens: &[Token], index: usize, what: &str) -> Result<(Vec<Elt>, usize), String> {
+    let mut elts = vec![];
+    let start_line_number = tokens[index].line_number;
+
+    // skip the opening brace
+    let mut index = index + 1;
+    loop {
+        if let Some(token) = tokens.get(index) {
+            if token._type == BraceR {
+                return Ok((elts, index + 1));
+            }
+
+            let (elt, new_index) = parse_expr(tokens, index)?;
+            elts.push(elt);
+            index = new_index;
+        } else {
+            return Err(format!(
+                "unterminated {} starting on line {}",
+                what, start_line_number
+            ));
+        }
+    }
+}
+
+fn parse_set(tokens: &[Token], index: usize) -> Result<(Elt, usize), String> {
+    assert_eq!(HashBraceL, tokens[index]._type);
+    let start_line_number = tokens[index].line_number;
+    let (elts, index) = parse_braced(tokens, index, "set")?;
+
+    let mut set = EltSet::default();
+    for elt in elts {
+        if set.insert(elt.clone()).is_some() {
+            return Err(format!(
+                "duplicate element {:?} in set literal starting on line {}",
+                elt, start_line_number
+            ));
+        }
+    }
+    Ok((Set(set), index))
+}
+
+fn parse_map(tokens: &[Token], index: usize) -> Result<(Elt, usize), String> {
+    assert_eq!(BraceL, tokens[index]._type);
+    let start_line_number = tokens[index].line_number;
+    let (elts, index) = parse_braced(tokens, index, "map")?;
+
+    if elts.len() % 2 != 0 {
+        return Err(format!(
+            "map literal starting on line {} requires an even number of forms",
+            start_line_number
+        ));
+    }
+
+    let mut map = EltMap::default();
+    for pair in elts.chunks(2) {
+        if map.insert(pair[0].clone(), pair[1].clone()).is_some() {
+            return Err(format!(
+                "duplicate key {:?} in map literal starting on line {}",
+                pair[0], start_line_number
+            ));
+        }
+    }
+    Ok((Map(map), index))
+}
+
+/// Renames `%` to `%1` within a `#()` body, recording the highest numbered parameter and
+/// whether `%&` is used.
+fn lambda_params(elt: Elt, arity: &mut usize, rest: &mut bool) -> Elt {
+    match elt {
+        Symbol(s) if s == "%" => {
+            *arity = (*arity).max(1);
+            Symbol("%1".to_string())
+        }
+        Symbol(s) if s == "%&" => {
+            *rest = true;
+            Symbol(s)
+        }
+        Symbol(s) => {
+            if let Some(n) = s.strip_prefix('%').and_then(|n| n.parse::<usize>().ok()) {
+                *arity = (*arity).max(n);
+            }
+            Symbol(s)
+        }
+        List(elts) => List(
+            elts.into_iter()
+                .map(|e| lambda_params(e, arity, rest))
+                .collect(),
+        ),
+        Vector(elts) => Vector(
+            elts.into_iter()
+                .map(|e| lambda_params(e, arity, rest))
+                .collect(),
+        ),
+        Map(map) => Map(map
+            .iter()
+            .map(|(k, v)| {
+                (
+                    lambda_params(k.clone(), arity, rest),
+                    lambda_params(v.clone(), arity, rest),
+                )
+            })
+            .collect()),
+        Set(set) => Set(set
+            .iter()
+            .map(|e| lambda_params(e.clone(), arity, rest))
+            .collect()),
+        _ => elt,
+    }
+}
+
+/// Parses `#(...)` into `(fn [%1 .. %n & %&] (...))`.
+fn parse_lambda(tokens: &[Token], index: usize) -> Result<(Elt, usize), String> {
+    assert_eq!(HashParenL, tokens[index]._type);
+
+    let mut depth = 0;
+    for token in &tokens[index + 1..] {
+        match token._type {
+            HashParenL => {
+                return Err(format!(
+                    "nested #() on line {}; #() lambdas cannot contain one another",
+                    token.line_number
+                ))
+            }
+            ParenL | BracketL | BraceL | HashBraceL => depth += 1,
+            ParenR | BracketR | BraceR if depth == 0 => break,
+            ParenR | BracketR | BraceR => depth -= 1,
+            _ => (),
+        }
+    }
+
+    let (body, index) = parse_list(tokens, index)?;
+    let (mut arity, mut rest) = (0, false);
+    let body = lambda_params(body, &mut arity, &mut rest);
+
+    let mut params: Vec<Elt> = (1..=arity).map(|n| Symbol(format!("%{}", n))).collect();
+    if rest {
+        params.push(Symbol("&".to_string()));
+        params.push(Symbol("%&".to_string()));
+    }
+    Ok((
+        List(vec![BuiltinFunction(Builtin::Fn_), Vector(params), body]),
+        index,
+    ))
+}
+
 fn parse_list(tokens: &[Token], index: usize) -> Result<(Elt, usize), String> {
     let mut elts = vec![];
-    assert_eq!(ParenL, tokens[index]._type);
+    assert!(matches!(tokens[index]._type, ParenL | HashParenL));
     let start_line_number = tokens[index].line_number;
 
     // skip the lparen
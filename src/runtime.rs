@@ -1,8 +1,29 @@
 use crate::model::*;
-use std::collections::HashMap;
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Arc;
+use std::thread;
 
-fn lookup(scope: &Scope, name: &String) -> Result<Elt, String> {
-    if let Some(value) = scope.bindings.get(name) {
+static GENSYM_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn lookup(name: &str, runtime: &Runtime, scope: &Scope) -> Result<Elt, String> {
+    let mut frame = Some(scope);
+    while let Some(f) = frame {
+        if let Some(value) = f.bindings.get(name) {
+            return Ok(value.clone());
+        }
+        frame = f.parent.as_deref();
+    }
+    if let Some(value) = runtime.root_scope.bindings.get(name) {
         return Ok(value.clone());
     }
     Err(format!("variable {:?} undefined", name))
@@ -22,6 +43,13 @@ fn format_with_spaces(elts: &[Elt]) -> String {
 pub fn format_elt(elt: &Elt) -> String {
     match elt {
         Elt::List(items) => {
+            // print reader shorthand back the way it was written
+            if let [head, inner] = &items[..] {
+                if let Some(prefix) = reader_prefix(head) {
+                    return format!("{}{}", prefix, format_elt(inner));
+                }
+            }
+
             let mut s = '('.to_string();
             s.push_str(&format_with_spaces(items));
             s.push(')');
@@ -33,63 +61,734 @@ pub fn format_elt(elt: &Elt) -> String {
             s.push(']');
             s
         }
+        Elt::Map(map) => {
+            let entries: Vec<Elt> = map
+                .iter()
+                .flat_map(|(k, v)| vec![k.clone(), v.clone()])
+                .collect();
+            let mut s = '{'.to_string();
+            s.push_str(&format_with_spaces(&entries));
+            s.push('}');
+            s
+        }
+        Elt::Set(set) => {
+            let elts: Vec<Elt> = set.iter().cloned().collect();
+            let mut s = "#{".to_string();
+            s.push_str(&format_with_spaces(&elts));
+            s.push('}');
+            s
+        }
         Elt::Bool(b) => format!("{}", b),
         Elt::Int(i) => format!("{}", i),
+        Elt::BigInt(b) => b.to_string(),
+        Elt::Ratio(r) => r.to_string(),
         Elt::Double(d) => format!("{}", d),
         Elt::String_(s) => s.clone(),
+        Elt::Char(c) => c.to_string(),
+        Elt::Byte(b) => format!("{}", b),
         Elt::Symbol(s) => s.clone(),
-        Elt::Nil => format!("nil"),
-        Elt::Function { .. } => format!("<function>"),
+        Elt::Keyword(s) => format!(":{}", s),
+        Elt::Nil => "nil".to_string(),
+        Elt::Function { .. } => "<function>".to_string(),
         Elt::BuiltinFunction(b) => format!("<builtin function {:?}>", b),
-        Elt::Macro { .. } => format!("<macro>"),
+        Elt::Macro { .. } => "<macro>".to_string(),
+    }
+}
+
+fn reader_prefix(elt: &Elt) -> Option<&'static str> {
+    match elt {
+        Elt::BuiltinFunction(Builtin::Quote) => Some("'"),
+        Elt::BuiltinFunction(Builtin::Quasiquote) => Some("`"),
+        Elt::BuiltinFunction(Builtin::Unquote) => Some("~"),
+        Elt::BuiltinFunction(Builtin::UnquoteSplicing) => Some("~@"),
+        _ => None,
     }
 }
 
-fn replace_symbol(elt: &Elt, symbol: &str, value: Elt) -> Elt {
+/// If `elt` is a form like `(~ x)` or `(unquote x)` whose head is or names `builtin`, returns
+/// `x`.
+fn prefixed_form<'a>(
+    elt: &'a Elt,
+    builtin: Builtin,
+    runtime: &Runtime,
+    scope: &Scope,
+) -> Option<&'a Elt> {
+    let builtin = Elt::BuiltinFunction(builtin);
     match elt {
-        Elt::Symbol(s) => {
-            if s == symbol {
-                value
+        Elt::List(elts) if elts.len() == 2 => {
+            let matches = match &elts[0] {
+                Elt::Symbol(name) => lookup(name, runtime, scope).is_ok_and(|v| v == builtin),
+                head => *head == builtin,
+            };
+            if matches {
+                Some(&elts[1])
             } else {
-                elt.clone()
+                None
             }
         }
-        Elt::List(l) => {
-            let mut new = vec![];
-            for item in l {
-                new.push(replace_symbol(&item, symbol, value.clone()));
+        _ => None,
+    }
+}
+
+/// Returns a symbol that cannot collide with any other symbol generated by this process.
+fn gensym(prefix: &str) -> Elt {
+    Elt::Symbol(format!(
+        "{}{}",
+        prefix,
+        GENSYM_COUNTER.fetch_add(1, atomic::Ordering::Relaxed)
+    ))
+}
+
+/// Expands the body of a quasiquoted form. `depth` counts the enclosing quasiquotes, so that
+/// only unquotes belonging to the outermost one are evaluated. Symbols ending in `#` are replaced
+/// by a generated symbol, the same one for every occurrence within the template.
+fn quasiquote(
+    form: &Elt,
+    depth: usize,
+    gensyms: &mut HashMap<String, Elt>,
+    runtime: &mut Runtime,
+    scope: &Arc<Scope>,
+) -> Result<Elt, String> {
+    if let Elt::Symbol(name) = form {
+        if let Some(stem) = name.strip_suffix('#') {
+            let generated = gensyms
+                .entry(name.clone())
+                .or_insert_with(|| gensym(&format!("{}__auto__", stem)));
+            return Ok(generated.clone());
+        }
+    }
+
+    if let Some(inner) = prefixed_form(form, Builtin::Unquote, runtime, scope) {
+        if depth == 1 {
+            return eval(inner, runtime, scope);
+        }
+        let inner = quasiquote(inner, depth - 1, gensyms, runtime, scope)?;
+        return Ok(Elt::List(vec![
+            Elt::BuiltinFunction(Builtin::Unquote),
+            inner,
+        ]));
+    }
+    if let Some(inner) = prefixed_form(form, Builtin::Quasiquote, runtime, scope) {
+        let inner = quasiquote(inner, depth + 1, gensyms, runtime, scope)?;
+        return Ok(Elt::List(vec![
+            Elt::BuiltinFunction(Builtin::Quasiquote),
+            inner,
+        ]));
+    }
+
+    let items = match form {
+        Elt::List(items) | Elt::Vector(items) => items,
+        Elt::Map(map) => {
+            return map_entries(map, |elt| quasiquote(elt, depth, gensyms, runtime, scope))
+        }
+        Elt::Set(set) => {
+            return set_elements(set, |elt| quasiquote(elt, depth, gensyms, runtime, scope))
+        }
+        _ => return Ok(form.clone()),
+    };
+
+    let mut expanded = vec![];
+    for item in items {
+        match prefixed_form(item, Builtin::UnquoteSplicing, runtime, scope) {
+            Some(inner) if depth == 1 => match eval(inner, runtime, scope)? {
+                Elt::List(elts) | Elt::Vector(elts) => expanded.extend(elts),
+                other => {
+                    return Err(format!(
+                        "~@ requires a list or vector; got {}",
+                        format_elt(&other)
+                    ))
+                }
+            },
+            Some(inner) => {
+                let inner = quasiquote(inner, depth - 1, gensyms, runtime, scope)?;
+                expanded.push(Elt::List(vec![
+                    Elt::BuiltinFunction(Builtin::UnquoteSplicing),
+                    inner,
+                ]));
             }
-            return Elt::List(new);
+            None => expanded.push(quasiquote(item, depth, gensyms, runtime, scope)?),
+        }
+    }
+
+    match form {
+        Elt::Vector(_) => Ok(Elt::Vector(expanded)),
+        _ => Ok(Elt::List(expanded)),
+    }
+}
+
+/// Builds a new map by applying `f` to every key and value of `map`.
+fn map_entries<F>(map: &EltMap, mut f: F) -> Result<Elt, String>
+where
+    F: FnMut(&Elt) -> Result<Elt, String>,
+{
+    let mut new = EltMap::default();
+    for (key, value) in map.iter() {
+        new.insert(f(key)?, f(value)?);
+    }
+    Ok(Elt::Map(new))
+}
+
+/// Builds a new set by applying `f` to every element of `set`.
+fn set_elements<F>(set: &EltSet, mut f: F) -> Result<Elt, String>
+where
+    F: FnMut(&Elt) -> Result<Elt, String>,
+{
+    let mut new = EltSet::default();
+    for elt in set.iter() {
+        new.insert(f(elt)?);
+    }
+    Ok(Elt::Set(new))
+}
+
+/// Accepts a set argument to a set builtin, treating nil as the empty set.
+fn expect_set(elt: Elt, builtin: &str) -> Result<EltSet, String> {
+    match elt {
+        Elt::Set(set) => Ok(set),
+        Elt::Nil => Ok(EltSet::default()),
+        _ => Err(format!("{} requires a set; got {:?}", builtin, elt)),
+    }
+}
+
+/// Accepts a map argument to a map builtin, treating nil as the empty map.
+fn expect_map(elt: Elt, builtin: &str) -> Result<EltMap, String> {
+    match elt {
+        Elt::Map(map) => Ok(map),
+        Elt::Nil => Ok(EltMap::default()),
+        _ => Err(format!("{} requires a map; got {:?}", builtin, elt)),
+    }
+}
+
+fn expect_string(elt: Elt, builtin: &str) -> Result<String, String> {
+    match elt {
+        Elt::String_(s) => Ok(s),
+        _ => Err(format!("{} requires a string; got {:?}", builtin, elt)),
+    }
+}
+
+fn expect_index(elt: Elt, builtin: &str) -> Result<usize, String> {
+    match elt {
+        Elt::Int(i) if i >= 0 => Ok(i as usize),
+        _ => Err(format!(
+            "{} requires a non-negative integer index; got {:?}",
+            builtin, elt
+        )),
+    }
+}
+
+/// How a value reads when concatenated by `str` and `str/join`; nil contributes nothing.
+fn str_fragment(elt: &Elt) -> String {
+    match elt {
+        Elt::Nil => String::new(),
+        _ => format_elt(elt),
+    }
+}
+
+fn io_error(builtin: &str, path: &str, e: std::io::Error) -> String {
+    format!("{} failed for {:?}: {}", builtin, path, e)
+}
+
+/// Calls `function` with an already-evaluated argument by quoting it into a call form.
+fn apply(
+    function: &Elt,
+    args: Vec<Elt>,
+    runtime: &mut Runtime,
+    scope: &Arc<Scope>,
+) -> Result<Elt, String> {
+    let mut call = vec![function.clone()];
+    for arg in args {
+        call.push(Elt::List(vec![Elt::BuiltinFunction(Builtin::Quote), arg]));
+    }
+    eval(&Elt::List(call), runtime, scope)
+}
+
+/// Accepts a sequence argument to a sequence builtin, treating nil as empty.
+fn expect_seq(elt: Elt, builtin: &str) -> Result<Vec<Elt>, String> {
+    match elt {
+        Elt::List(elts) | Elt::Vector(elts) => Ok(elts),
+        Elt::Nil => Ok(vec![]),
+        _ => Err(format!("{} requires a list; got {:?}", builtin, elt)),
+    }
+}
+
+// evaluation recurses on the Rust stack, so workers get as much as the main thread
+const WORKER_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Applies `function` to each element on up to one thread per CPU, returning the results in
+/// order. Workers share the globals read-only, so `function` may not `def`. The first failure,
+/// in element order, is returned.
+fn parallel_apply(
+    name: &str,
+    function: &Elt,
+    elts: &[Elt],
+    runtime: &Runtime,
+    scope: &Arc<Scope>,
+) -> Result<Vec<Elt>, String> {
+    if elts.is_empty() {
+        return Ok(vec![]);
+    }
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = elts.len().div_ceil(workers);
+
+    thread::scope(|s| {
+        let mut handles = vec![];
+        for chunk in elts.chunks(chunk_size) {
+            let mut runtime = Runtime {
+                root_scope: runtime.root_scope.clone(),
+                in_worker: true,
+            };
+            let handle = thread::Builder::new()
+                .stack_size(WORKER_STACK_SIZE)
+                .spawn_scoped(s, move || {
+                    let mut results = vec![];
+                    for elt in chunk {
+                        let result = apply(function, vec![elt.clone()], &mut runtime, scope)
+                            .map_err(|e| {
+                                format!("{} failed on {}: {}", name, format_elt(elt), e)
+                            })?;
+                        results.push(result);
+                    }
+                    Ok::<_, String>(results)
+                })
+                .map_err(|e| format!("{} could not start a worker: {}", name, e))?;
+            handles.push(handle);
         }
-        Elt::Vector(v) => {
-            let mut new = vec![];
-            for item in v {
-                new.push(replace_symbol(&item, symbol, value.clone()));
+
+        let mut results = vec![];
+        for handle in handles {
+            match handle.join() {
+                Ok(chunk_results) => results.extend(chunk_results?),
+                Err(_) => return Err(format!("{} worker panicked", name)),
             }
-            return Elt::Vector(new);
         }
-        _ => elt.clone(),
+        Ok(results)
+    })
+}
+
+fn eval_args(args: &[Elt], runtime: &mut Runtime, scope: &Arc<Scope>) -> Result<Vec<Elt>, String> {
+    let mut values = vec![];
+    for arg in args {
+        values.push(eval(arg, runtime, scope)?);
     }
+    Ok(values)
 }
 
 fn truthy(elt: &Elt) -> bool {
+    !matches!(elt, Elt::Nil | Elt::Bool(false))
+}
+
+fn parse_pattern(elt: &Elt, form: &str) -> Result<Pattern, String> {
     match elt {
-        Elt::Nil => false,
-        Elt::Bool(false) => false,
-        _ => true,
+        Elt::Symbol(s) if s != "&" => Ok(Pattern::Symbol(s.clone())),
+        Elt::Vector(items) => {
+            let (items, rest) = parse_params(items, form)?;
+            Ok(Pattern::Sequence { items, rest })
+        }
+        _ => Err(format!(
+            "only symbols and vectors allowed in {} binding vector; found {}",
+            form,
+            format_elt(elt)
+        )),
+    }
+}
+
+/// Splits a binding vector like `[a [b c] & more]` into its positional patterns and the optional
+/// pattern that collects any remaining elements.
+fn parse_params(
+    params: &[Elt],
+    form: &str,
+) -> Result<(Vec<Pattern>, Option<Box<Pattern>>), String> {
+    let mut patterns = vec![];
+    let mut iter = params.iter();
+    while let Some(param) = iter.next() {
+        if let Elt::Symbol(s) = param {
+            if s == "&" {
+                return match (iter.next(), iter.next()) {
+                    (Some(rest), None) => {
+                        Ok((patterns, Some(Box::new(parse_pattern(rest, form)?))))
+                    }
+                    _ => Err(format!(
+                        "& in {} binding vector must be followed by exactly one binding",
+                        form
+                    )),
+                };
+            }
+        }
+        patterns.push(parse_pattern(param, form)?);
+    }
+    Ok((patterns, None))
+}
+
+fn destructure(
+    pattern: &Pattern,
+    value: Elt,
+    bindings: &mut HashMap<String, Elt>,
+) -> Result<(), String> {
+    match pattern {
+        Pattern::Symbol(name) => {
+            bindings.insert(name.clone(), value);
+            Ok(())
+        }
+        Pattern::Sequence { items, rest } => match value {
+            Elt::List(elts) | Elt::Vector(elts) => destructure_seq(items, rest, elts, bindings),
+            _ => Err(format!(
+                "cannot destructure {} with a vector pattern; expected a list or vector",
+                format_elt(&value)
+            )),
+        },
+    }
+}
+
+fn destructure_seq(
+    items: &[Pattern],
+    rest: &Option<Box<Pattern>>,
+    mut elts: Vec<Elt>,
+    bindings: &mut HashMap<String, Elt>,
+) -> Result<(), String> {
+    if elts.len() < items.len() || (rest.is_none() && elts.len() > items.len()) {
+        return Err(format!(
+            "cannot destructure {}: pattern expects {}{} elements, found {}",
+            format_elt(&Elt::List(elts.clone())),
+            items.len(),
+            if rest.is_some() { " or more" } else { "" },
+            elts.len()
+        ));
     }
+
+    let tail = elts.split_off(items.len());
+    for (pattern, elt) in items.iter().zip(elts) {
+        destructure(pattern, elt, bindings)?;
+    }
+    if let Some(rest) = rest {
+        destructure(rest, Elt::List(tail), bindings)?;
+    }
+    Ok(())
+}
+
+/// Builds a clause from a binding vector followed by one or more body forms.
+fn parse_clause(forms: &[Elt], form: &str) -> Result<Clause, String> {
+    if forms.len() < 2 {
+        return Err(format!(
+            "{} requires a binding vector and a body; {} forms found",
+            form,
+            forms.len()
+        ));
+    }
+
+    if let Elt::Vector(ref params) = forms[0] {
+        let (lexical_bindings, rest_binding) = parse_params(params, form)?;
+        Ok(Clause {
+            lexical_bindings,
+            rest_binding,
+            body: Arc::new(implicit_do(&forms[1..])),
+        })
+    } else {
+        Err(format!(
+            "{} requires a vector of symbols as its first parameter",
+            form
+        ))
+    }
+}
+
+fn accepts(clause: &Clause, received: usize) -> bool {
+    if clause.rest_binding.is_some() {
+        received >= clause.lexical_bindings.len()
+    } else {
+        received == clause.lexical_bindings.len()
+    }
+}
+
+/// Picks the clause to run for a call with `received` arguments. A clause with exactly that many
+/// fixed parameters wins over one that collects the arguments with `&`.
+fn select_clause<'a>(
+    function: &Elt,
+    clauses: &'a [Clause],
+    received: usize,
+) -> Result<&'a Clause, String> {
+    let exact = clauses
+        .iter()
+        .find(|c| c.rest_binding.is_none() && accepts(c, received));
+    if let Some(clause) = exact.or_else(|| clauses.iter().find(|c| accepts(c, received))) {
+        return Ok(clause);
+    }
+
+    let arities: Vec<String> = clauses
+        .iter()
+        .map(|c| match c.rest_binding {
+            Some(_) => format!("{}+", c.lexical_bindings.len()),
+            None => format!("{}", c.lexical_bindings.len()),
+        })
+        .collect();
+    Err(format!(
+        "{} expects {} parameters-- received {}",
+        format_elt(function),
+        arities.join(" or "),
+        received
+    ))
+}
+
+/// Parses the arguments of `fn` or `macro`: either `[params] body...` or one `([params] body...)`
+/// clause per arity.
+fn parse_clauses(args: &[Elt], form: &str) -> Result<Vec<Clause>, String> {
+    if let Some(Elt::List(_)) = args.first() {
+        let mut clauses = vec![];
+        for arg in args {
+            if let Elt::List(forms) = arg {
+                clauses.push(parse_clause(forms, form)?);
+            } else {
+                return Err(format!(
+                    "expected a ([params] body) clause in {}; found {:?}",
+                    form, arg
+                ));
+            }
+        }
+        Ok(clauses)
+    } else {
+        Ok(vec![parse_clause(args, form)?])
+    }
+}
+
+/// Wraps a sequence of body forms in a `do` so they can be stored and evaluated as one form.
+fn implicit_do(forms: &[Elt]) -> Elt {
+    if forms.len() == 1 {
+        return forms[0].clone();
+    }
+    let mut elts = vec![Elt::BuiltinFunction(Builtin::Do)];
+    elts.extend_from_slice(forms);
+    Elt::List(elts)
 }
 
 fn to_double(elt: &Elt) -> Result<f64, String> {
     match elt {
-        Elt::Int(i) => Ok(i.clone() as f64),
-        Elt::Double(d) => Ok(d.clone()),
-        _ => return Err(format!("expected number, got {:?}", elt)),
+        Elt::Int(i) => Ok(*i as f64),
+        Elt::BigInt(b) => Ok(b.to_f64().unwrap_or(f64::NAN)),
+        Elt::Ratio(r) => Ok(r.to_f64().unwrap_or(f64::NAN)),
+        Elt::Double(d) => Ok(*d),
+        _ => Err(format!("expected number, got {:?}", elt)),
+    }
+}
+
+fn to_bigint(elt: &Elt) -> Result<BigInt, String> {
+    match elt {
+        Elt::Int(i) => Ok(BigInt::from(*i)),
+        Elt::BigInt(b) => Ok(b.clone()),
+        _ => Err(format!("expected integer, got {:?}", elt)),
+    }
+}
+
+/// Narrows a big integer back to `Elt::Int` when it fits.
+fn demote(b: BigInt) -> Elt {
+    match b.to_i64() {
+        Some(i) => Elt::Int(i),
+        None => Elt::BigInt(b),
+    }
+}
+
+fn to_ratio(elt: &Elt) -> Result<BigRational, String> {
+    match elt {
+        Elt::Ratio(r) => Ok(r.clone()),
+        _ => Ok(BigRational::from_integer(to_bigint(elt)?)),
+    }
+}
+
+/// Narrows a fraction to an integer when its denominator is 1.
+fn demote_ratio(r: BigRational) -> Elt {
+    if r.is_integer() {
+        demote(r.to_integer())
+    } else {
+        Elt::Ratio(r)
+    }
+}
+
+struct ArithOp {
+    name: &'static str,
+    int: fn(i64, i64) -> Option<i64>,
+    ratio: fn(&BigRational, &BigRational) -> BigRational,
+    double: fn(f64, f64) -> f64,
+}
+
+const ADD: ArithOp = ArithOp {
+    name: "addition",
+    int: i64::checked_add,
+    ratio: |a, b| a + b,
+    double: |a, b| a + b,
+};
+
+const SUB: ArithOp = ArithOp {
+    name: "subtraction",
+    int: i64::checked_sub,
+    ratio: |a, b| a - b,
+    double: |a, b| a - b,
+};
+
+const MUL: ArithOp = ArithOp {
+    name: "multiplication",
+    int: i64::checked_mul,
+    ratio: |a, b| a * b,
+    double: |a, b| a * b,
+};
+
+// only reached once the divisor is known to be non-zero
+const DIV: ArithOp = ArithOp {
+    name: "division",
+    int: |a, b| match a.checked_rem(b) {
+        Some(0) => a.checked_div(b),
+        _ => None,
+    },
+    ratio: |a, b| a / b,
+    double: |a, b| a / b,
+};
+
+fn is_number(elt: &Elt) -> bool {
+    matches!(
+        elt,
+        Elt::Int(_) | Elt::BigInt(_) | Elt::Ratio(_) | Elt::Double(_)
+    )
+}
+
+fn expect_number(elt: Elt, op: &ArithOp) -> Result<Elt, String> {
+    if is_number(&elt) {
+        Ok(elt)
+    } else {
+        Err(format!("attempt to perform {} on {:?}", op.name, elt))
+    }
+}
+
+/// Applies `op` to two numbers. Anything not representable as an `i64` is computed exactly as a
+/// fraction and narrowed back to an integer where possible; doubles are contagious.
+fn arithmetic(op: &ArithOp, a: Elt, b: Elt) -> Result<Elt, String> {
+    let a = expect_number(a, op)?;
+    let b = expect_number(b, op)?;
+    if let (Elt::Int(x), Elt::Int(y)) = (&a, &b) {
+        if let Some(i) = (op.int)(*x, *y) {
+            return Ok(Elt::Int(i));
+        }
+    }
+    match (&a, &b) {
+        (Elt::Double(_), _) | (_, Elt::Double(_)) => {
+            Ok(Elt::Double((op.double)(to_double(&a)?, to_double(&b)?)))
+        }
+        _ => Ok(demote_ratio((op.ratio)(&to_ratio(&a)?, &to_ratio(&b)?))),
+    }
+}
+
+/// Orders two numbers, exactly unless either is a double. `None` if either is NaN or not a
+/// number.
+fn compare_numbers(a: &Elt, b: &Elt) -> Option<Ordering> {
+    match (a, b) {
+        (Elt::Int(x), Elt::Int(y)) => Some(x.cmp(y)),
+        (Elt::Double(_), _) | (_, Elt::Double(_)) => {
+            to_double(a).ok()?.partial_cmp(&to_double(b).ok()?)
+        }
+        _ => Some(to_ratio(a).ok()?.cmp(&to_ratio(b).ok()?)),
+    }
+}
+
+/// Position of each kind of value in the order used by `compare`; `None` for values that
+/// cannot be ordered.
+fn type_rank(elt: &Elt) -> Option<u8> {
+    Some(match elt {
+        Elt::Nil => 0,
+        Elt::Bool(_) => 1,
+        Elt::Int(_) | Elt::BigInt(_) | Elt::Ratio(_) | Elt::Double(_) => 2,
+        Elt::Char(_) => 3,
+        Elt::Byte(_) => 4,
+        Elt::String_(_) => 5,
+        Elt::Symbol(_) => 6,
+        Elt::Keyword(_) => 7,
+        Elt::List(_) => 8,
+        Elt::Vector(_) => 9,
+        Elt::Map(_) => 10,
+        Elt::Set(_) => 11,
+        Elt::Function { .. } | Elt::BuiltinFunction(_) | Elt::Macro { .. } => return None,
+    })
+}
+
+fn compare_seqs(a: &[Elt], b: &[Elt]) -> Result<Ordering, String> {
+    for (x, y) in a.iter().zip(b) {
+        match compare_elts(x, y)? {
+            Ordering::Equal => continue,
+            ordering => return Ok(ordering),
+        }
+    }
+    Ok(a.len().cmp(&b.len()))
+}
+
+fn sorted(mut elts: Vec<Elt>) -> Result<Vec<Elt>, String> {
+    let mut error = None;
+    elts.sort_by(|a, b| {
+        compare_elts(a, b).unwrap_or_else(|e| {
+            error.get_or_insert(e);
+            Ordering::Equal
+        })
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(elts),
+    }
+}
+
+/// A total order over values: by kind first, then numerically, lexically, or element by element
+/// within a kind. Maps and sets are compared by their sorted contents.
+fn compare_elts(a: &Elt, b: &Elt) -> Result<Ordering, String> {
+    let (rank_a, rank_b) = match (type_rank(a), type_rank(b)) {
+        (Some(x), Some(y)) => (x, y),
+        _ => {
+            return Err(format!(
+                "cannot compare {} and {}",
+                format_elt(a),
+                format_elt(b)
+            ))
+        }
+    };
+    if rank_a != rank_b {
+        return Ok(rank_a.cmp(&rank_b));
     }
+
+    Ok(match (a, b) {
+        (Elt::Bool(x), Elt::Bool(y)) => x.cmp(y),
+        (Elt::Char(x), Elt::Char(y)) => x.cmp(y),
+        (Elt::Byte(x), Elt::Byte(y)) => x.cmp(y),
+        (Elt::String_(x), Elt::String_(y))
+        | (Elt::Symbol(x), Elt::Symbol(y))
+        | (Elt::Keyword(x), Elt::Keyword(y)) => x.cmp(y),
+        (Elt::List(x), Elt::List(y)) | (Elt::Vector(x), Elt::Vector(y)) => compare_seqs(x, y)?,
+        (Elt::Map(x), Elt::Map(y)) => {
+            let entries = |map: &EltMap| {
+                map.iter()
+                    .map(|(k, v)| Elt::Vector(vec![k.clone(), v.clone()]))
+                    .collect()
+            };
+            compare_seqs(&sorted(entries(x))?, &sorted(entries(y))?)?
+        }
+        (Elt::Set(x), Elt::Set(y)) => compare_seqs(
+            &sorted(x.iter().cloned().collect())?,
+            &sorted(y.iter().cloned().collect())?,
+        )?,
+        // NaN sorts above every other number
+        _ if is_number(a) => compare_numbers(a, b).unwrap_or_else(|| {
+            let nan = |n: &Elt| to_double(n).is_ok_and(f64::is_nan);
+            nan(a).cmp(&nan(b))
+        }),
+        _ => Ordering::Equal,
+    })
+}
+
+/// The outcome of evaluating one form. Forms in tail position are handed back to `eval` rather
+/// than evaluated recursively, so tail-recursive code runs in constant Rust stack. A `Tail`
+/// borrows a subform of the form being evaluated (the chosen branch of an `if`); a `TailCall`
+/// holds a form that lives elsewhere (the body of a function).
+enum Step<'a> {
+    Done(Elt),
+    Tail(&'a Elt, Arc<Scope>),
+    TailCall(Arc<Elt>, Arc<Scope>),
 }
 
-fn eval_function(elts: &[Elt], runtime: &mut Runtime, scope: &Scope) -> Result<Elt, String> {
-    if elts.len() == 0 {
+fn eval_function<'a>(
+    elts: &'a [Elt],
+    runtime: &mut Runtime,
+    scope: &Arc<Scope>,
+) -> Result<Step<'a>, String> {
+    if elts.is_empty() {
         return Err("attempt to evaluate empty list as function".to_string());
     }
 
@@ -97,86 +796,61 @@ fn eval_function(elts: &[Elt], runtime: &mut Runtime, scope: &Scope) -> Result<E
 
     match function {
         Elt::Function {
-            ref lexical_bindings,
-            ref body,
+            ref clauses,
+            ref env,
         } => {
             let mut args = vec![];
             for elt in &elts[1..] {
-                args.push(eval(&elt, runtime, scope)?);
+                args.push(eval(elt, runtime, scope)?);
             }
 
-            if lexical_bindings.len() != args.len() {
-                return Err(format!(
-                    "{:?} expects {} parameters-- received {}",
-                    function,
-                    lexical_bindings.len(),
-                    args.len()
-                ));
-            }
+            let clause = select_clause(&function, clauses, args.len())?;
 
-            let mut new_scope = scope.clone();
-            let mut i = 0;
-            while i < lexical_bindings.len() {
-                new_scope
-                    .bindings
-                    .insert(lexical_bindings[i].clone(), args[i].clone());
-                i += 1;
-            }
+            // the body sees the scope the function was defined in, not the caller's
+            let mut new_scope = Scope {
+                bindings: HashMap::new(),
+                parent: Some(env.clone()),
+            };
+            destructure_seq(
+                &clause.lexical_bindings,
+                &clause.rest_binding,
+                args,
+                &mut new_scope.bindings,
+            )?;
 
-            return eval(body, runtime, &new_scope);
+            Ok(Step::TailCall(clause.body.clone(), Arc::new(new_scope)))
         }
 
         Elt::BuiltinFunction(btype) => {
             let args = &elts[1..];
 
-            match btype {
+            let result = match btype {
                 Builtin::Def => {
                     if args.len() != 2 {
                         return Err(format!("expected 2 arguments to def; {} found", args.len()));
                     }
 
+                    if runtime.in_worker {
+                        return Err("def cannot be used inside a parallel worker".to_string());
+                    }
+
                     if let Elt::Symbol(sym) = &args[0] {
                         let val = &eval(&args[1], runtime, scope)?;
-                        runtime.root_scope.bindings.insert(sym.clone(), val.clone());
+                        Arc::make_mut(&mut runtime.root_scope)
+                            .bindings
+                            .insert(sym.clone(), val.clone());
                         Ok(val.clone())
                     } else {
-                        Err(format!("first parameter to def must be a symbol"))
-                    }
-                }
-                Builtin::Macro => {
-                    if args.len() != 2 {
-                        return Err(format!(
-                            "expected 2 arguments to defmacro; {} found",
-                            args.len()
-                        ));
-                    }
-
-                    if let Elt::Vector(ref params) = args[0] {
-                        let mut lexical_bindings = vec![];
-                        for param in params {
-                            if let Elt::Symbol(s) = param {
-                                lexical_bindings.push(s.clone());
-                            } else {
-                                return Err(
-                                    "only symbols allowed in macro binding vector".to_string()
-                                );
-                            }
-                        }
-
-                        Ok(Elt::Macro {
-                            lexical_bindings,
-                            body: Box::new(args[1].clone()),
-                        })
-                    } else {
-                        Err(
-                            "defmacro requires a vector of symbols as its first parameter"
-                                .to_string(),
-                        )
+                        Err("first parameter to def must be a symbol".to_string())
                     }
                 }
+                Builtin::Macro => Ok(Elt::Macro {
+                    clauses: parse_clauses(args, "macro")?.into(),
+                    env: scope.clone(),
+                }),
                 Builtin::Print => {
                     for arg in args {
-                        let elt = eval(&arg, runtime, scope)?;
+                        let elt = eval(arg, runtime, scope)?;
                         print!("{}", format_elt(&elt));
                         print!(" ");
                     }
@@ -184,7 +858,7 @@ fn eval_function(elts: &[Elt], runtime: &mut Runtime, scope: &Scope) -> Result<E
                 }
                 Builtin::Println => {
                     for arg in args {
-                        let elt = eval(&arg, runtime, scope)?;
+                        let elt = eval(arg, runtime, scope)?;
                         print!("{}", format_elt(&elt));
                         print!(" ");
                     }
@@ -200,29 +874,115 @@ fn eval_function(elts: &[Elt], runtime: &mut Runtime, scope: &Scope) -> Result<E
                     }
                     Ok(args[0].clone())
                 }
+                Builtin::Quasiquote => {
+                    if args.len() != 1 {
+                        return Err(format!(
+                            "quasiquote accepts only one parameter; {} found",
+                            args.len()
+                        ));
+                    }
+                    quasiquote(&args[0], 1, &mut HashMap::new(), runtime, scope)
+                }
+                Builtin::Unquote => Err("~ used outside of a quasiquoted form".to_string()),
+                Builtin::UnquoteSplicing => {
+                    Err("~@ used outside of a quasiquoted form".to_string())
+                }
 
-                Builtin::Fn_ => {
-                    if args.len() != 2 {
-                        return Err(format!("fn requires 2 parameters; {} found", args.len()));
+                Builtin::Fn_ => Ok(Elt::Function {
+                    clauses: parse_clauses(args, "fn")?.into(),
+                    env: scope.clone(),
+                }),
+
+                Builtin::MacroExpand1 => {
+                    if args.len() != 1 {
+                        return Err(format!(
+                            "macroexpand-1 takes one parameter; {} found",
+                            args.len()
+                        ));
                     }
 
-                    if let Elt::Vector(ref params) = args[0] {
-                        let mut lexical_bindings = vec![];
-                        for param in params {
-                            if let Elt::Symbol(s) = param {
-                                lexical_bindings.push(s.clone());
-                            } else {
-                                return Err("only symbols allowed in fn binding vector".to_string());
-                            }
+                    let form = eval(&args[0], runtime, scope)?;
+                    Ok(macroexpand_1(&form, runtime, scope)?.unwrap_or(form))
+                }
+
+                Builtin::MacroExpand => {
+                    if args.len() != 1 {
+                        return Err(format!(
+                            "macroexpand takes one parameter; {} found",
+                            args.len()
+                        ));
+                    }
+
+                    let form = eval(&args[0], runtime, scope)?;
+                    macroexpand(&form, runtime, scope)
+                }
+
+                Builtin::Gensym => {
+                    if args.len() > 1 {
+                        return Err(format!(
+                            "gensym takes at most one parameter; {} found",
+                            args.len()
+                        ));
+                    }
+
+                    match args.first() {
+                        Some(arg) => match eval(arg, runtime, scope)? {
+                            Elt::String_(prefix) | Elt::Symbol(prefix) => Ok(gensym(&prefix)),
+                            other => Err(format!(
+                                "gensym prefix must be a string or symbol; got {:?}",
+                                other
+                            )),
+                        },
+                        None => Ok(gensym("G__")),
+                    }
+                }
+
+                Builtin::Let => {
+                    if args.len() < 2 {
+                        return Err(format!(
+                            "let requires at least 2 parameters; {} found",
+                            args.len()
+                        ));
+                    }
+
+                    if let Elt::Vector(ref bindings) = args[0] {
+                        if bindings.len() % 2 != 0 {
+                            return Err(
+                                "let binding vector requires an even number of forms".to_string()
+                            );
+                        }
+
+                        // each binding gets its own frame so later values can see earlier names
+                        let mut let_scope = scope.clone();
+                        for pair in bindings.chunks(2) {
+                            let pattern = parse_pattern(&pair[0], "let")?;
+                            let value = eval(&pair[1], runtime, &let_scope)?;
+                            let mut frame = Scope {
+                                bindings: HashMap::new(),
+                                parent: Some(let_scope),
+                            };
+                            destructure(&pattern, value, &mut frame.bindings)?;
+                            let_scope = Arc::new(frame);
                         }
 
-                        Ok(Elt::Function {
-                            lexical_bindings,
-                            body: Box::new(args[1].clone()),
-                        })
+                        let (last, init) = args[1..].split_last().unwrap();
+                        for arg in init {
+                            eval(arg, runtime, &let_scope)?;
+                        }
+                        return Ok(Step::Tail(last, let_scope));
                     } else {
-                        Err("fn requires a vector of symbols as its first parameter".to_string())
+                        Err("let requires a vector of bindings as its first parameter".to_string())
+                    }
+                }
+
+                Builtin::Do => {
+                    if let Some((last, init)) = args.split_last() {
+                        for arg in init {
+                            eval(arg, runtime, scope)?;
+                        }
+                        return Ok(Step::Tail(last, scope.clone()));
                     }
+                    Ok(Elt::Nil)
                 }
 
                 Builtin::If => {
@@ -232,14 +992,12 @@ fn eval_function(elts: &[Elt], runtime: &mut Runtime, scope: &Scope) -> Result<E
 
                     let condition = eval(&args[0], runtime, scope)?;
                     if truthy(&condition) {
-                        eval(&args[1], runtime, scope)
-                    } else {
-                        if args.len() == 3 {
-                            eval(&args[2], runtime, scope)
-                        } else {
-                            Ok(Elt::Nil)
-                        }
+                        return Ok(Step::Tail(&args[1], scope.clone()));
+                    }
+                    if args.len() == 3 {
+                        return Ok(Step::Tail(&args[2], scope.clone()));
                     }
+                    Ok(Elt::Nil)
                 }
 
                 Builtin::Not => {
@@ -259,7 +1017,7 @@ fn eval_function(elts: &[Elt], runtime: &mut Runtime, scope: &Scope) -> Result<E
 
                     let list = eval(&args[0], runtime, scope)?;
                     if let Elt::List(ref elts) = list {
-                        if elts.len() == 0 {
+                        if elts.is_empty() {
                             Err("attempt to car empty list".to_string())
                         } else {
                             Ok(elts[0].clone())
@@ -298,7 +1056,7 @@ fn eval_function(elts: &[Elt], runtime: &mut Runtime, scope: &Scope) -> Result<E
                         new_list.append(&mut old_list);
                         Ok(Elt::List(new_list))
                     } else {
-                        return Err(format!("second arg to cons must be a list; got {:?}", list));
+                        Err(format!("second arg to cons must be a list; got {:?}", list))
                     }
                 }
 
@@ -309,198 +1067,765 @@ fn eval_function(elts: &[Elt], runtime: &mut Runtime, scope: &Scope) -> Result<E
 
                     let list = eval(&args[0], runtime, scope)?;
                     if let Elt::List(ref elts) = list {
-                        Ok(Elt::Bool(elts.len() == 0))
+                        Ok(Elt::Bool(elts.is_empty()))
                     } else {
-                        return Err(format!("arg to empty? must be a list; got {:?}", list));
+                        Err(format!("arg to empty? must be a list; got {:?}", list))
                     }
                 }
 
-                Builtin::Nth => {
-                    if args.len() != 2 {
-                        return Err(format!("nth takes two parameters; {} found", args.len()));
+                Builtin::List_ => {
+                    if args.len() != 1 {
+                        return Err(format!("list? takes one parameter; {} found", args.len()));
                     }
 
-                    let list = eval(&args[0], runtime, scope)?;
-                    if let Elt::List(ref elts) = list {
-                        let index = eval(&args[1], runtime, scope)?;
-                        if let Elt::Int(i) = index {
-                            match elts.get(i as usize) {
-                                Some(v) => Ok(v.clone()),
-                                _ => Err("index out of bounds".to_string()),
-                            }
-                        } else {
-                            Err(format!(
-                                "nth requires integer second param; got {:?}",
-                                index
+                    let value = eval(&args[0], runtime, scope)?;
+                    Ok(Elt::Bool(matches!(value, Elt::List(_))))
+                }
+
+                Builtin::Map | Builtin::Filter => {
+                    let name = if btype == Builtin::Map {
+                        "map"
+                    } else {
+                        "filter"
+                    };
+                    if args.len() != 2 {
+                        return Err(format!(
+                            "{} takes a function and a list; {} parameters found",
+                            name,
+                            args.len()
+                        ));
+                    }
+
+                    let function = eval(&args[0], runtime, scope)?;
+                    let elts = expect_seq(eval(&args[1], runtime, scope)?, name)?;
+                    let mut results = vec![];
+                    for elt in elts {
+                        let result = apply(&function, vec![elt.clone()], runtime, scope)?;
+                        if btype == Builtin::Map {
+                            results.push(result);
+                        } else if truthy(&result) {
+                            results.push(elt);
+                        }
+                    }
+                    Ok(Elt::List(results))
+                }
+
+                Builtin::Foldl => {
+                    if args.len() != 3 {
+                        return Err(format!(
+                            "foldl takes a function, an initial value and a list; {} parameters \
+                             found",
+                            args.len()
+                        ));
+                    }
+
+                    let function = eval(&args[0], runtime, scope)?;
+                    let mut acc = eval(&args[1], runtime, scope)?;
+                    for elt in expect_seq(eval(&args[2], runtime, scope)?, "foldl")? {
+                        acc = apply(&function, vec![acc, elt], runtime, scope)?;
+                    }
+                    Ok(acc)
+                }
+
+                Builtin::Reverse => {
+                    if args.len() != 1 {
+                        return Err(format!("reverse takes one parameter; {} found", args.len()));
+                    }
+
+                    let mut elts = expect_seq(eval(&args[0], runtime, scope)?, "reverse")?;
+                    elts.reverse();
+                    Ok(Elt::List(elts))
+                }
+
+                Builtin::Range => {
+                    let (start, end) = match &eval_args(args, runtime, scope)?[..] {
+                        [Elt::Int(end)] => (0, *end),
+                        [Elt::Int(start), Elt::Int(end)] => (*start, *end),
+                        values => {
+                            return Err(format!(
+                                "range takes an integer end, or start and end; got {:?}",
+                                values
                             ))
                         }
-                    } else {
-                        Err(format!(
-                            "nth requires a list as its first parameter; {:?} found",
-                            list
-                        ))
+                    };
+                    Ok(Elt::List((start..end).map(Elt::Int).collect()))
+                }
+
+                Builtin::HashMap_ => {
+                    if !args.len().is_multiple_of(2) {
+                        return Err("hash-map requires an even number of parameters".to_string());
                     }
+
+                    let values = eval_args(args, runtime, scope)?;
+                    let mut map = EltMap::default();
+                    for pair in values.chunks(2) {
+                        map.insert(pair[0].clone(), pair[1].clone());
+                    }
+                    Ok(Elt::Map(map))
                 }
 
-                Builtin::Plus => {
-                    let mut is_double = false;
-                    let mut acc_int = 0i64;
-                    let mut acc_double = 0f64;
+                Builtin::Get => {
+                    if args.len() < 2 || args.len() > 3 {
+                        return Err(format!("get requires 2-3 parameters; {} found", args.len()));
+                    }
 
-                    for arg in args {
-                        match eval(&arg, runtime, scope)? {
-                            Elt::Double(d) => {
-                                if !is_double {
-                                    acc_double = acc_int as f64;
-                                    is_double = true;
-                                }
-                                acc_double += d;
-                            }
-                            Elt::Int(i) => {
-                                if is_double {
-                                    acc_double += i as f64;
-                                } else {
-                                    acc_int += i;
-                                }
+                    let mut values = eval_args(args, runtime, scope)?.into_iter();
+                    let map = expect_map(values.next().unwrap_or(Elt::Nil), "get")?;
+                    let key = values.next().unwrap_or(Elt::Nil);
+                    let default = values.next().unwrap_or(Elt::Nil);
+                    Ok(map.get(&key).cloned().unwrap_or(default))
+                }
+
+                Builtin::Assoc => {
+                    if args.len() < 3 || args.len().is_multiple_of(2) {
+                        return Err(format!(
+                            "assoc requires a map followed by key-value pairs; {} parameters found",
+                            args.len()
+                        ));
+                    }
+
+                    let values = eval_args(args, runtime, scope)?;
+                    let mut map = expect_map(values[0].clone(), "assoc")?;
+                    for pair in values[1..].chunks(2) {
+                        map.insert(pair[0].clone(), pair[1].clone());
+                    }
+                    Ok(Elt::Map(map))
+                }
+
+                Builtin::Dissoc => {
+                    if args.is_empty() {
+                        return Err("dissoc requires a map".to_string());
+                    }
+
+                    let values = eval_args(args, runtime, scope)?;
+                    let mut map = expect_map(values[0].clone(), "dissoc")?;
+                    for key in &values[1..] {
+                        map.remove(key);
+                    }
+                    Ok(Elt::Map(map))
+                }
+
+                Builtin::Keys => {
+                    if args.len() != 1 {
+                        return Err(format!("keys takes one parameter; {} found", args.len()));
+                    }
+
+                    let map = expect_map(eval(&args[0], runtime, scope)?, "keys")?;
+                    Ok(Elt::List(map.keys().cloned().collect()))
+                }
+
+                Builtin::Vals => {
+                    if args.len() != 1 {
+                        return Err(format!("vals takes one parameter; {} found", args.len()));
+                    }
+
+                    let map = expect_map(eval(&args[0], runtime, scope)?, "vals")?;
+                    Ok(Elt::List(map.values().cloned().collect()))
+                }
+
+                Builtin::Contains_ => {
+                    if args.len() != 2 {
+                        return Err(format!(
+                            "contains? takes two parameters; {} found",
+                            args.len()
+                        ));
+                    }
+
+                    let coll = eval(&args[0], runtime, scope)?;
+                    let key = eval(&args[1], runtime, scope)?;
+                    match coll {
+                        Elt::Set(set) => Ok(Elt::Bool(set.contains(&key))),
+                        coll => Ok(Elt::Bool(expect_map(coll, "contains?")?.contains_key(&key))),
+                    }
+                }
+
+                Builtin::HashSet_ => {
+                    let values = eval_args(args, runtime, scope)?;
+                    Ok(Elt::Set(values.into_iter().collect()))
+                }
+
+                Builtin::Conj => {
+                    if args.is_empty() {
+                        return Err("conj requires a collection".to_string());
+                    }
+
+                    let mut values = eval_args(args, runtime, scope)?;
+                    let items = values.split_off(1);
+                    match values.remove(0) {
+                        // lists grow at the front, vectors at the back, as in Clojure
+                        Elt::List(mut elts) => {
+                            for item in items {
+                                elts.insert(0, item);
                             }
-                            x => return Err(format!("attempt to perform addition on {:?}", x)),
+                            Ok(Elt::List(elts))
+                        }
+                        Elt::Vector(mut elts) => {
+                            elts.extend(items);
+                            Ok(Elt::Vector(elts))
+                        }
+                        coll => {
+                            let mut set = expect_set(coll, "conj")?;
+                            set.extend(items);
+                            Ok(Elt::Set(set))
                         }
                     }
-                    if is_double {
-                        Ok(Elt::Double(acc_double))
-                    } else {
-                        Ok(Elt::Int(acc_int))
+                }
+
+                Builtin::Disj => {
+                    if args.is_empty() {
+                        return Err("disj requires a set".to_string());
+                    }
+
+                    let values = eval_args(args, runtime, scope)?;
+                    let mut set = expect_set(values[0].clone(), "disj")?;
+                    for item in &values[1..] {
+                        set.remove(item);
                     }
+                    Ok(Elt::Set(set))
                 }
 
-                Builtin::Minus => {
-                    let mut first = true;
-                    let mut is_double = false;
-                    let mut acc_int = 0i64;
-                    let mut acc_double = 0f64;
+                Builtin::Union | Builtin::Intersection | Builtin::Difference => {
+                    let name = match btype {
+                        Builtin::Union => "union",
+                        Builtin::Intersection => "intersection",
+                        _ => "difference",
+                    };
+                    if args.is_empty() {
+                        return Err(format!("{} requires at least one set", name));
+                    }
 
-                    for arg in args {
-                        match eval(&arg, runtime, scope)? {
-                            Elt::Double(d) => {
-                                if first {
-                                    is_double = true;
-                                    acc_double = d;
-                                    first = false;
-                                } else {
-                                    if !is_double {
-                                        acc_double = acc_int as f64;
-                                        is_double = true;
-                                    }
-                                    acc_double -= d;
-                                }
-                            }
-                            Elt::Int(i) => {
-                                if first {
-                                    acc_int = i;
-                                    first = false;
-                                } else {
-                                    if is_double {
-                                        acc_double -= i as f64;
-                                    } else {
-                                        acc_int -= i;
-                                    }
-                                }
-                            }
-                            x => return Err(format!("attempt to perform subtraction on {:?}", x)),
+                    let mut sets = vec![];
+                    for value in eval_args(args, runtime, scope)? {
+                        sets.push(expect_set(value, name)?);
+                    }
+                    let mut result = sets[0].clone();
+                    for set in &sets[1..] {
+                        match btype {
+                            Builtin::Union => result.extend(set.iter().cloned()),
+                            Builtin::Intersection => result.retain(|elt| set.contains(elt)),
+                            _ => result.retain(|elt| !set.contains(elt)),
                         }
                     }
-                    if is_double {
-                        Ok(Elt::Double(acc_double))
-                    } else {
-                        Ok(Elt::Int(acc_int))
+                    Ok(Elt::Set(result))
+                }
+
+                Builtin::Nth => {
+                    if args.len() != 2 {
+                        return Err(format!("nth takes two parameters; {} found", args.len()));
                     }
+
+                    let seq = eval(&args[0], runtime, scope)?;
+                    let index = match eval(&args[1], runtime, scope)? {
+                        Elt::Int(i) if i >= 0 => i as usize,
+                        Elt::Int(_) => return Err("index out of bounds".to_string()),
+                        index => {
+                            return Err(format!(
+                                "nth requires integer second param; got {:?}",
+                                index
+                            ))
+                        }
+                    };
+                    let found = match seq {
+                        Elt::List(ref elts) | Elt::Vector(ref elts) => elts.get(index).cloned(),
+                        // strings are indexed by character, not by byte
+                        Elt::String_(ref s) => s.chars().nth(index).map(Elt::Char),
+                        _ => {
+                            return Err(format!(
+                                "nth requires a list, vector or string as its first parameter; \
+                                 {:?} found",
+                                seq
+                            ))
+                        }
+                    };
+                    found.ok_or_else(|| "index out of bounds".to_string())
                 }
 
-                Builtin::Mult => {
-                    let mut is_double = false;
-                    let mut acc_int = 1i64;
-                    let mut acc_double = 1f64;
+                Builtin::Count => {
+                    if args.len() != 1 {
+                        return Err(format!("count takes one parameter; {} found", args.len()));
+                    }
 
-                    for arg in args {
-                        match eval(&arg, runtime, scope)? {
-                            Elt::Double(d) => {
-                                if !is_double {
-                                    acc_double = acc_int as f64;
-                                    is_double = true;
-                                }
-                                acc_double *= d;
+                    let count = match eval(&args[0], runtime, scope)? {
+                        Elt::List(elts) | Elt::Vector(elts) => elts.len(),
+                        Elt::String_(s) => s.chars().count(),
+                        Elt::Map(map) => map.len(),
+                        Elt::Set(set) => set.len(),
+                        Elt::Nil => 0,
+                        other => return Err(format!("count not supported on {:?}", other)),
+                    };
+                    Ok(Elt::Int(count as i64))
+                }
+
+                Builtin::Str => {
+                    let values = eval_args(args, runtime, scope)?;
+                    Ok(Elt::String_(values.iter().map(str_fragment).collect()))
+                }
+
+                Builtin::StrCat => {
+                    let mut s = String::new();
+                    for value in eval_args(args, runtime, scope)? {
+                        s.push_str(&expect_string(value, "str/cat")?);
+                    }
+                    Ok(Elt::String_(s))
+                }
+
+                Builtin::StrSubstr => {
+                    if args.len() < 2 || args.len() > 3 {
+                        return Err(format!(
+                            "str/substr requires 2-3 parameters; {} found",
+                            args.len()
+                        ));
+                    }
+
+                    let mut values = eval_args(args, runtime, scope)?.into_iter();
+                    let s = expect_string(values.next().unwrap_or(Elt::Nil), "str/substr")?;
+                    let len = s.chars().count();
+                    let start = expect_index(values.next().unwrap_or(Elt::Nil), "str/substr")?;
+                    let end = match values.next() {
+                        Some(end) => expect_index(end, "str/substr")?,
+                        None => len,
+                    };
+                    if start > end || end > len {
+                        return Err(format!(
+                            "str/substr range {}..{} out of bounds for string of {} characters",
+                            start, end, len
+                        ));
+                    }
+                    // indices count characters, not bytes
+                    Ok(Elt::String_(
+                        s.chars().skip(start).take(end - start).collect(),
+                    ))
+                }
+
+                Builtin::StrTrim | Builtin::StrUpper | Builtin::StrLower => {
+                    let name = match btype {
+                        Builtin::StrTrim => "str/trim",
+                        Builtin::StrUpper => "str/upper",
+                        _ => "str/lower",
+                    };
+                    if args.len() != 1 {
+                        return Err(format!(
+                            "{} takes one parameter; {} found",
+                            name,
+                            args.len()
+                        ));
+                    }
+
+                    let s = expect_string(eval(&args[0], runtime, scope)?, name)?;
+                    Ok(Elt::String_(match btype {
+                        Builtin::StrTrim => s.trim().to_string(),
+                        Builtin::StrUpper => s.to_uppercase(),
+                        _ => s.to_lowercase(),
+                    }))
+                }
+
+                Builtin::StrContains
+                | Builtin::StrStartsWith
+                | Builtin::StrEndsWith
+                | Builtin::StrIndexOf
+                | Builtin::StrSplit => {
+                    let name = match btype {
+                        Builtin::StrContains => "str/contains",
+                        Builtin::StrStartsWith => "str/starts-with?",
+                        Builtin::StrEndsWith => "str/ends-with?",
+                        Builtin::StrIndexOf => "str/index-of",
+                        _ => "str/split",
+                    };
+                    if args.len() != 2 {
+                        return Err(format!(
+                            "{} takes two parameters; {} found",
+                            name,
+                            args.len()
+                        ));
+                    }
+
+                    let s = expect_string(eval(&args[0], runtime, scope)?, name)?;
+                    let part = expect_string(eval(&args[1], runtime, scope)?, name)?;
+                    Ok(match btype {
+                        Builtin::StrContains => Elt::Bool(s.contains(&part)),
+                        Builtin::StrStartsWith => Elt::Bool(s.starts_with(&part)),
+                        Builtin::StrEndsWith => Elt::Bool(s.ends_with(&part)),
+                        Builtin::StrIndexOf => match s.find(&part) {
+                            Some(i) => Elt::Int(s[..i].chars().count() as i64),
+                            None => Elt::Nil,
+                        },
+                        // an empty separator splits the string into its characters
+                        _ if part.is_empty() => {
+                            Elt::List(s.chars().map(|c| Elt::String_(c.to_string())).collect())
+                        }
+                        _ => Elt::List(
+                            s.split(&part[..])
+                                .map(|piece| Elt::String_(piece.to_string()))
+                                .collect(),
+                        ),
+                    })
+                }
+
+                Builtin::StrReplace => {
+                    if args.len() != 3 {
+                        return Err(format!(
+                            "str/replace takes three parameters; {} found",
+                            args.len()
+                        ));
+                    }
+
+                    let mut values = eval_args(args, runtime, scope)?.into_iter();
+                    let mut next =
+                        || expect_string(values.next().unwrap_or(Elt::Nil), "str/replace");
+                    let (s, from, to) = (next()?, next()?, next()?);
+                    if from.is_empty() {
+                        return Err("str/replace cannot replace an empty string".to_string());
+                    }
+                    Ok(Elt::String_(s.replace(&from, &to)))
+                }
+
+                Builtin::StrJoin => {
+                    if args.len() != 2 {
+                        return Err(format!(
+                            "str/join takes a separator and a list; {} parameters found",
+                            args.len()
+                        ));
+                    }
+
+                    let separator = expect_string(eval(&args[0], runtime, scope)?, "str/join")?;
+                    match eval(&args[1], runtime, scope)? {
+                        Elt::List(elts) | Elt::Vector(elts) => Ok(Elt::String_(
+                            elts.iter()
+                                .map(str_fragment)
+                                .collect::<Vec<_>>()
+                                .join(&separator),
+                        )),
+                        other => Err(format!("str/join requires a list; got {:?}", other)),
+                    }
+                }
+
+                Builtin::IoSlurp
+                | Builtin::IoLines
+                | Builtin::IoListDir
+                | Builtin::IoExists
+                | Builtin::IoDelete => {
+                    let name = match btype {
+                        Builtin::IoSlurp => "io/slurp",
+                        Builtin::IoLines => "io/lines",
+                        Builtin::IoListDir => "io/list-dir",
+                        Builtin::IoExists => "io/exists?",
+                        _ => "io/delete",
+                    };
+                    if args.len() != 1 {
+                        return Err(format!(
+                            "{} takes a path; {} parameters found",
+                            name,
+                            args.len()
+                        ));
+                    }
+
+                    let path = expect_string(eval(&args[0], runtime, scope)?, name)?;
+                    let io_error = |e| io_error(name, &path, e);
+                    match btype {
+                        Builtin::IoSlurp => fs::read_to_string(&path)
+                            .map(Elt::String_)
+                            .map_err(io_error),
+                        Builtin::IoLines => {
+                            let file = fs::File::open(&path).map_err(io_error)?;
+                            let mut lines = vec![];
+                            for line in BufReader::new(file).lines() {
+                                lines.push(Elt::String_(line.map_err(io_error)?));
                             }
-                            Elt::Int(i) => {
-                                if is_double {
-                                    acc_double *= i as f64;
-                                } else {
-                                    acc_int *= i;
-                                }
+                            Ok(Elt::List(lines))
+                        }
+                        Builtin::IoListDir => {
+                            let mut names = vec![];
+                            for entry in fs::read_dir(&path).map_err(io_error)? {
+                                let entry = entry.map_err(io_error)?;
+                                names.push(entry.file_name().to_string_lossy().into_owned());
                             }
-                            x => {
-                                return Err(format!("attempt to perform multiplication on {:?}", x))
+                            names.sort();
+                            Ok(Elt::List(names.into_iter().map(Elt::String_).collect()))
+                        }
+                        Builtin::IoExists => Ok(Elt::Bool(Path::new(&path).exists())),
+                        _ => {
+                            // directories are only removed when empty
+                            if Path::new(&path).is_dir() {
+                                fs::remove_dir(&path).map_err(io_error)?;
+                            } else {
+                                fs::remove_file(&path).map_err(io_error)?;
                             }
+                            Ok(Elt::Nil)
                         }
                     }
-                    if is_double {
-                        Ok(Elt::Double(acc_double))
+                }
+
+                Builtin::IoSpit | Builtin::IoAppend => {
+                    let name = if btype == Builtin::IoSpit {
+                        "io/spit"
                     } else {
-                        Ok(Elt::Int(acc_int))
+                        "io/append"
+                    };
+                    if args.len() != 2 {
+                        return Err(format!(
+                            "{} takes a path and contents; {} parameters found",
+                            name,
+                            args.len()
+                        ));
                     }
+
+                    let path = expect_string(eval(&args[0], runtime, scope)?, name)?;
+                    let contents = str_fragment(&eval(&args[1], runtime, scope)?);
+                    fs::OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .append(btype == Builtin::IoAppend)
+                        .truncate(btype == Builtin::IoSpit)
+                        .open(&path)
+                        .and_then(|mut file| file.write_all(contents.as_bytes()))
+                        .map_err(|e| io_error(name, &path, e))?;
+                    Ok(Elt::Nil)
                 }
 
-                Builtin::Div => {
-                    let mut first = true;
-                    let mut acc_double = 0f64;
+                Builtin::ParallelMap | Builtin::ParallelFilter => {
+                    let name = if btype == Builtin::ParallelMap {
+                        "parallel-map"
+                    } else {
+                        "parallel-filter"
+                    };
+                    if args.len() != 2 {
+                        return Err(format!(
+                            "{} takes a function and a list; {} parameters found",
+                            name,
+                            args.len()
+                        ));
+                    }
+
+                    let function = eval(&args[0], runtime, scope)?;
+                    let elts = match eval(&args[1], runtime, scope)? {
+                        Elt::List(elts) | Elt::Vector(elts) => elts,
+                        other => return Err(format!("{} requires a list; got {:?}", name, other)),
+                    };
+                    let results = parallel_apply(name, &function, &elts, runtime, scope)?;
+                    if btype == Builtin::ParallelMap {
+                        Ok(Elt::List(results))
+                    } else {
+                        Ok(Elt::List(
+                            elts.into_iter()
+                                .zip(results)
+                                .filter(|(_, keep)| truthy(keep))
+                                .map(|(elt, _)| elt)
+                                .collect(),
+                        ))
+                    }
+                }
+
+                Builtin::Char => {
+                    if args.len() != 1 {
+                        return Err(format!("char takes one parameter; {} found", args.len()));
+                    }
+
+                    let code = match eval(&args[0], runtime, scope)? {
+                        Elt::Char(c) => return Ok(Step::Done(Elt::Char(c))),
+                        Elt::Byte(b) => u32::from(b),
+                        Elt::Int(i) if i >= 0 && i <= i64::from(u32::MAX) => i as u32,
+                        other => return Err(format!("cannot convert {:?} to a char", other)),
+                    };
+                    std::char::from_u32(code)
+                        .map(Elt::Char)
+                        .ok_or_else(|| format!("{} is not a unicode scalar value", code))
+                }
+
+                Builtin::Byte => {
+                    if args.len() != 1 {
+                        return Err(format!("byte takes one parameter; {} found", args.len()));
+                    }
 
+                    let value = eval(&args[0], runtime, scope)?;
+                    let code = match value {
+                        Elt::Byte(b) => return Ok(Step::Done(Elt::Byte(b))),
+                        Elt::Char(c) => i64::from(u32::from(c)),
+                        Elt::Int(i) => i,
+                        _ => return Err(format!("cannot convert {:?} to a byte", value)),
+                    };
+                    match u8::try_from(code) {
+                        Ok(b) => Ok(Elt::Byte(b)),
+                        Err(_) => Err(format!("{} does not fit in a byte", format_elt(&value))),
+                    }
+                }
+
+                Builtin::Int => {
+                    if args.len() != 1 {
+                        return Err(format!("int takes one parameter; {} found", args.len()));
+                    }
+
+                    match eval(&args[0], runtime, scope)? {
+                        Elt::Int(i) => Ok(Elt::Int(i)),
+                        Elt::BigInt(b) => Ok(Elt::BigInt(b)),
+                        Elt::Ratio(r) => Ok(demote(r.to_integer())),
+                        Elt::Char(c) => Ok(Elt::Int(i64::from(u32::from(c)))),
+                        Elt::Byte(b) => Ok(Elt::Int(i64::from(b))),
+                        Elt::Double(d) => BigInt::from_f64(d.trunc())
+                            .map(demote)
+                            .ok_or_else(|| format!("cannot convert {} to an int", d)),
+                        other => Err(format!("cannot convert {:?} to an int", other)),
+                    }
+                }
+
+                Builtin::Plus => {
+                    let mut acc = Elt::Int(0);
                     for arg in args {
-                        match eval(&arg, runtime, scope)? {
-                            Elt::Double(d) => {
-                                if first {
-                                    acc_double = d;
-                                    first = false;
-                                } else {
-                                    acc_double /= d;
-                                }
-                            }
-                            Elt::Int(i) => {
-                                if first {
-                                    acc_double = i as f64;
-                                    first = false;
-                                } else {
-                                    acc_double /= i as f64;
-                                }
-                            }
-                            x => return Err(format!("attempt to perform division on {:?}", x)),
+                        acc = arithmetic(&ADD, acc, eval(arg, runtime, scope)?)?;
+                    }
+                    Ok(acc)
+                }
+
+                Builtin::Minus => {
+                    let mut acc = None;
+                    for arg in args {
+                        let value = eval(arg, runtime, scope)?;
+                        acc = Some(match acc {
+                            None => expect_number(value, &SUB)?,
+                            Some(acc) => arithmetic(&SUB, acc, value)?,
+                        });
+                    }
+                    Ok(acc.unwrap_or(Elt::Int(0)))
+                }
+
+                Builtin::Mult => {
+                    let mut acc = Elt::Int(1);
+                    for arg in args {
+                        acc = arithmetic(&MUL, acc, eval(arg, runtime, scope)?)?;
+                    }
+                    Ok(acc)
+                }
+
+                Builtin::Div => {
+                    let mut values = vec![];
+                    for arg in args {
+                        values.push(expect_number(eval(arg, runtime, scope)?, &DIV)?);
+                    }
+                    // a single double makes the whole division inexact, where dividing by
+                    // zero gives an infinity or NaN rather than an error
+                    if values.iter().any(|v| matches!(v, Elt::Double(_))) {
+                        for value in values.iter_mut() {
+                            *value = Elt::Double(to_double(value)?);
                         }
                     }
-                    Ok(Elt::Double(acc_double))
+
+                    let mut values = values.into_iter();
+                    let mut acc = values
+                        .next()
+                        .ok_or_else(|| "division (/) requires parameters".to_string())?;
+                    for value in values {
+                        // exact zeroes are always demoted to Int(0)
+                        if value == Elt::Int(0) {
+                            return Err("division by zero".to_string());
+                        }
+                        acc = arithmetic(&DIV, acc, value)?;
+                    }
+                    Ok(acc)
+                }
+
+                Builtin::Numerator | Builtin::Denominator => {
+                    let name = if btype == Builtin::Numerator {
+                        "numerator"
+                    } else {
+                        "denominator"
+                    };
+                    if args.len() != 1 {
+                        return Err(format!(
+                            "{} takes one parameter; {} found",
+                            name,
+                            args.len()
+                        ));
+                    }
+
+                    let r = match eval(&args[0], runtime, scope)? {
+                        n @ (Elt::Int(_) | Elt::BigInt(_) | Elt::Ratio(_)) => to_ratio(&n)?,
+                        other => return Err(format!("expected integer or ratio, got {:?}", other)),
+                    };
+                    Ok(demote(if btype == Builtin::Numerator {
+                        r.numer().clone()
+                    } else {
+                        r.denom().clone()
+                    }))
+                }
+
+                Builtin::Quot | Builtin::Rem | Builtin::Mod => {
+                    let name = match btype {
+                        Builtin::Quot => "quot",
+                        Builtin::Rem => "rem",
+                        _ => "mod",
+                    };
+                    if args.len() != 2 {
+                        return Err(format!(
+                            "{} takes two parameters; {} found",
+                            name,
+                            args.len()
+                        ));
+                    }
+
+                    let dividend = to_bigint(&eval(&args[0], runtime, scope)?)?;
+                    let divisor = to_bigint(&eval(&args[1], runtime, scope)?)?;
+                    if divisor.is_zero() {
+                        return Err("division by zero".to_string());
+                    }
+                    // quot and rem truncate toward zero; mod takes the sign of the divisor
+                    Ok(demote(match btype {
+                        Builtin::Quot => dividend / divisor,
+                        Builtin::Rem => dividend % divisor,
+                        _ => dividend.mod_floor(&divisor),
+                    }))
                 }
 
                 Builtin::Equal => {
-                    if args.len() == 0 {
+                    if args.is_empty() {
                         return Err("equality function (=) requires parameters".to_string());
                     }
 
                     let first = eval(&args[0], runtime, scope)?;
                     for arg in &args[1..] {
-                        let elt = eval(&arg, runtime, scope)?;
+                        let elt = eval(arg, runtime, scope)?;
                         if first != elt {
-                            return Ok(Elt::Bool(false));
+                            return Ok(Step::Done(Elt::Bool(false)));
                         }
                     }
                     Ok(Elt::Bool(true))
                 }
 
-                Builtin::GT => {
+                Builtin::NumEq | Builtin::LT | Builtin::GT | Builtin::LTEq | Builtin::GTEq => {
+                    let (name, holds): (&str, fn(Ordering) -> bool) = match btype {
+                        Builtin::NumEq => ("==", Ordering::is_eq),
+                        Builtin::LT => ("<", Ordering::is_lt),
+                        Builtin::GT => (">", Ordering::is_gt),
+                        Builtin::LTEq => ("<=", Ordering::is_le),
+                        _ => (">=", Ordering::is_ge),
+                    };
+                    if args.is_empty() {
+                        return Err(format!("{} requires parameters", name));
+                    }
+
+                    let values = eval_args(args, runtime, scope)?;
+                    if let Some(value) = values.iter().find(|v| !is_number(v)) {
+                        return Err(format!("{} requires numbers; got {:?}", name, value));
+                    }
+                    // every adjacent pair must be in order; NaN is never in order
+                    Ok(Elt::Bool(values.windows(2).all(|pair| {
+                        compare_numbers(&pair[0], &pair[1]).is_some_and(holds)
+                    })))
+                }
+
+                Builtin::Compare => {
                     if args.len() != 2 {
-                        return Err("> requires 2 parameters".to_string());
+                        return Err(format!(
+                            "compare takes two parameters; {} found",
+                            args.len()
+                        ));
                     }
 
                     let first = eval(&args[0], runtime, scope)?;
                     let second = eval(&args[1], runtime, scope)?;
-
-                    Ok(Elt::Bool(to_double(&first)? > to_double(&second)?))
+                    Ok(Elt::Int(match compare_elts(&first, &second)? {
+                        Ordering::Less => -1,
+                        Ordering::Equal => 0,
+                        Ordering::Greater => 1,
+                    }))
                 }
 
                 Builtin::Assert => {
@@ -532,65 +1857,483 @@ fn eval_function(elts: &[Elt], runtime: &mut Runtime, scope: &Scope) -> Result<E
                         ))
                     }
                 }
-            }
+            };
+            result.map(Step::Done)
         }
 
-        Elt::Macro {
-            ref lexical_bindings,
-            ref body,
-        } => {
-            let args = &elts[1..];
-            if lexical_bindings.len() != args.len() {
-                return Err(format!(
-                    "{:?} expects {} parameters-- received {}",
-                    function,
-                    lexical_bindings.len(),
-                    args.len()
-                ));
-            }
+        // macros are normally expanded before evaluation starts; this handles those that were
+        // not yet defined at that point, or that reach eval through `eval`
+        Elt::Macro { .. } => {
+            let expansion = expand_macro(&function, &elts[1..], runtime)?;
+            Ok(Step::TailCall(Arc::new(expansion), scope.clone()))
+        }
+
+        _ => Err(format!("attempt to treat {:?} as function", function)),
+    }
+}
 
-            let mut i = 0;
-            let mut replaced: Elt = *body.clone();
-            while i < lexical_bindings.len() {
-                replaced = replace_symbol(&replaced, &lexical_bindings[i], args[i].clone());
-                i += 1
+/// Runs a macro's body with its parameters bound to the unevaluated argument forms, returning
+/// the form to evaluate in their place.
+fn expand_macro(function: &Elt, args: &[Elt], runtime: &mut Runtime) -> Result<Elt, String> {
+    if let Elt::Macro { clauses, env } = function {
+        let clause = select_clause(function, clauses, args.len())?;
+
+        let mut macro_scope = Scope {
+            bindings: HashMap::new(),
+            parent: Some(env.clone()),
+        };
+        destructure_seq(
+            &clause.lexical_bindings,
+            &clause.rest_binding,
+            args.to_vec(),
+            &mut macro_scope.bindings,
+        )?;
+
+        eval(&clause.body, runtime, &Arc::new(macro_scope))
+    } else {
+        Err(format!(
+            "attempt to expand {} as a macro",
+            format_elt(function)
+        ))
+    }
+}
+
+/// The value in head position of a list form, if it can be resolved without evaluating anything.
+fn resolve_head(form: &Elt, runtime: &Runtime, scope: &Scope) -> Option<Elt> {
+    match form {
+        Elt::List(elts) => match elts.first()? {
+            Elt::Symbol(name) => lookup(name, runtime, scope).ok(),
+            head => Some(head.clone()),
+        },
+        _ => None,
+    }
+}
+
+/// Expands `form` once if it is a macro call, or returns `None` if it is not.
+fn macroexpand_1(
+    form: &Elt,
+    runtime: &mut Runtime,
+    scope: &Arc<Scope>,
+) -> Result<Option<Elt>, String> {
+    match (resolve_head(form, runtime, scope), form) {
+        (Some(head @ Elt::Macro { .. }), Elt::List(elts)) => {
+            expand_macro(&head, &elts[1..], runtime).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Expands `form` until its head is no longer a macro.
+fn macroexpand(form: &Elt, runtime: &mut Runtime, scope: &Arc<Scope>) -> Result<Elt, String> {
+    let mut form = form.clone();
+    while let Some(expanded) = macroexpand_1(&form, runtime, scope)? {
+        form = expanded;
+    }
+    Ok(form)
+}
+
+/// True if `form` is a call whose head names a local binding, which shadows any macro of the
+/// same name.
+fn shadowed_head(form: &Elt, locals: &HashSet<String>) -> bool {
+    match form {
+        Elt::List(items) => {
+            matches!(items.first(), Some(Elt::Symbol(name)) if locals.contains(name))
+        }
+        _ => false,
+    }
+}
+
+/// Adds the names bound by a binding pattern to `locals`.
+fn bound_names(pattern: &Elt, locals: &mut HashSet<String>) {
+    match pattern {
+        Elt::Symbol(name) if name != "&" => {
+            locals.insert(name.clone());
+        }
+        Elt::Vector(items) => items.iter().for_each(|item| bound_names(item, locals)),
+        _ => (),
+    }
+}
+
+/// Expands every macro call within `form`, leaving quoted data untouched. Names in `locals`
+/// are bound by an enclosing `fn`, `macro` or `let` and are never treated as macros.
+fn macroexpand_all(
+    form: &Elt,
+    locals: &HashSet<String>,
+    runtime: &mut Runtime,
+    scope: &Arc<Scope>,
+) -> Result<Elt, String> {
+    let mut form = form.clone();
+    while !shadowed_head(&form, locals) {
+        match macroexpand_1(&form, runtime, scope)? {
+            Some(expanded) => form = expanded,
+            None => break,
+        }
+    }
+
+    let head = if shadowed_head(&form, locals) {
+        None
+    } else {
+        resolve_head(&form, runtime, scope)
+    };
+    if let (Some(Elt::BuiltinFunction(builtin)), Elt::List(items)) = (&head, &form) {
+        match builtin {
+            Builtin::Quote => return Ok(form),
+            Builtin::Quasiquote if items.len() == 2 => {
+                let inner = macroexpand_unquoted(&items[1], 1, locals, runtime, scope)?;
+                return Ok(Elt::List(vec![items[0].clone(), inner]));
+            }
+            Builtin::Fn_ | Builtin::Macro | Builtin::Let => {
+                return macroexpand_binding_form(builtin, items, locals, runtime, scope)
             }
+            _ => (),
+        }
+    }
+
+    match form {
+        Elt::List(items) => Ok(Elt::List(macroexpand_each(&items, locals, runtime, scope)?)),
+        Elt::Vector(items) => Ok(Elt::Vector(macroexpand_each(
+            &items, locals, runtime, scope,
+        )?)),
+        Elt::Map(map) => map_entries(&map, |elt| macroexpand_all(elt, locals, runtime, scope)),
+        Elt::Set(set) => set_elements(&set, |elt| macroexpand_all(elt, locals, runtime, scope)),
+        _ => Ok(form),
+    }
+}
 
-            return eval(&replaced, runtime, scope);
+fn macroexpand_each(
+    items: &[Elt],
+    locals: &HashSet<String>,
+    runtime: &mut Runtime,
+    scope: &Arc<Scope>,
+) -> Result<Vec<Elt>, String> {
+    items
+        .iter()
+        .map(|item| macroexpand_all(item, locals, runtime, scope))
+        .collect()
+}
+
+/// Expands a `[params] body...` clause with its parameters added to `locals`.
+fn macroexpand_clause(
+    forms: &[Elt],
+    locals: &HashSet<String>,
+    runtime: &mut Runtime,
+    scope: &Arc<Scope>,
+) -> Result<Vec<Elt>, String> {
+    let mut locals = locals.clone();
+    bound_names(&forms[0], &mut locals);
+    let mut expanded = vec![forms[0].clone()];
+    expanded.extend(macroexpand_each(&forms[1..], &locals, runtime, scope)?);
+    Ok(expanded)
+}
+
+/// Expands a `fn`, `macro` or `let` form, leaving its binding patterns alone and treating the
+/// names they bind as locals within the forms they scope over.
+fn macroexpand_binding_form(
+    builtin: &Builtin,
+    items: &[Elt],
+    locals: &HashSet<String>,
+    runtime: &mut Runtime,
+    scope: &Arc<Scope>,
+) -> Result<Elt, String> {
+    let mut expanded = vec![items[0].clone()];
+    match (builtin, items.get(1)) {
+        (Builtin::Let, Some(Elt::Vector(bindings))) => {
+            // each binding is visible to the ones after it
+            let mut locals = locals.clone();
+            let mut new_bindings = vec![];
+            for pair in bindings.chunks(2) {
+                new_bindings.push(pair[0].clone());
+                if let Some(init) = pair.get(1) {
+                    new_bindings.push(macroexpand_all(init, &locals, runtime, scope)?);
+                }
+                bound_names(&pair[0], &mut locals);
+            }
+            expanded.push(Elt::Vector(new_bindings));
+            expanded.extend(macroexpand_each(&items[2..], &locals, runtime, scope)?);
         }
+        (Builtin::Fn_ | Builtin::Macro, Some(Elt::Vector(_))) => {
+            expanded.extend(macroexpand_clause(&items[1..], locals, runtime, scope)?);
+        }
+        (Builtin::Fn_ | Builtin::Macro, _) => {
+            for clause in &items[1..] {
+                expanded.push(match clause {
+                    Elt::List(forms) if matches!(forms.first(), Some(Elt::Vector(_))) => {
+                        Elt::List(macroexpand_clause(forms, locals, runtime, scope)?)
+                    }
+                    _ => macroexpand_all(clause, locals, runtime, scope)?,
+                });
+            }
+        }
+        _ => expanded.extend(macroexpand_each(&items[1..], locals, runtime, scope)?),
+    }
+    Ok(Elt::List(expanded))
+}
 
-        _ => {
-            return Err(format!("attempt to treat {:?} as function", function));
+/// Expands macros only within the parts of a quasiquoted template that will be evaluated.
+fn macroexpand_unquoted(
+    form: &Elt,
+    depth: usize,
+    locals: &HashSet<String>,
+    runtime: &mut Runtime,
+    scope: &Arc<Scope>,
+) -> Result<Elt, String> {
+    let builtins: &[Builtin] = if shadowed_head(form, locals) {
+        &[]
+    } else {
+        &[
+            Builtin::Unquote,
+            Builtin::UnquoteSplicing,
+            Builtin::Quasiquote,
+        ]
+    };
+    for builtin in builtins {
+        if let Some(inner) = prefixed_form(form, builtin.clone(), runtime, scope) {
+            let inner = match (builtin, depth) {
+                (Builtin::Quasiquote, _) => {
+                    macroexpand_unquoted(inner, depth + 1, locals, runtime, scope)?
+                }
+                (_, 1) => macroexpand_all(inner, locals, runtime, scope)?,
+                _ => macroexpand_unquoted(inner, depth - 1, locals, runtime, scope)?,
+            };
+            return Ok(Elt::List(vec![
+                Elt::BuiltinFunction(builtin.clone()),
+                inner,
+            ]));
         }
     }
+
+    let mut each = |items: &[Elt]| -> Result<Vec<Elt>, String> {
+        items
+            .iter()
+            .map(|item| macroexpand_unquoted(item, depth, locals, runtime, scope))
+            .collect()
+    };
+    match form {
+        Elt::List(items) => Ok(Elt::List(each(items)?)),
+        Elt::Vector(items) => Ok(Elt::Vector(each(items)?)),
+        Elt::Map(map) => map_entries(map, |elt| {
+            macroexpand_unquoted(elt, depth, locals, runtime, scope)
+        }),
+        Elt::Set(set) => set_elements(set, |elt| {
+            macroexpand_unquoted(elt, depth, locals, runtime, scope)
+        }),
+        _ => Ok(form.clone()),
+    }
 }
 
-pub fn eval(value: &Elt, runtime: &mut Runtime, scope: &Scope) -> Result<Elt, String> {
+fn eval_step<'a>(
+    value: &'a Elt,
+    runtime: &mut Runtime,
+    scope: &Arc<Scope>,
+) -> Result<Step<'a>, String> {
     match value {
         Elt::List(elts) => eval_function(elts, runtime, scope),
-        Elt::Symbol(name) => lookup(scope, &name),
-        _ => Ok(value.clone()),
+        Elt::Symbol(name) => lookup(name, runtime, scope).map(Step::Done),
+        Elt::Vector(elts) => {
+            eval_args(elts, runtime, scope).map(|elts| Step::Done(Elt::Vector(elts)))
+        }
+        Elt::Map(map) => map_entries(map, |elt| eval(elt, runtime, scope)).map(Step::Done),
+        Elt::Set(set) => set_elements(set, |elt| eval(elt, runtime, scope)).map(Step::Done),
+        _ => Ok(Step::Done(value.clone())),
+    }
+}
+
+pub fn eval(value: &Elt, runtime: &mut Runtime, scope: &Arc<Scope>) -> Result<Elt, String> {
+    // the body currently being evaluated, which `Tail` steps borrow from
+    let mut body: Arc<Elt>;
+    let mut step = eval_step(value, runtime, scope)?;
+    loop {
+        match step {
+            Step::Done(elt) => return Ok(elt),
+            Step::Tail(value, scope) => step = eval_step(value, runtime, &scope)?,
+            Step::TailCall(value, scope) => {
+                body = value;
+                step = eval_step(&body, runtime, &scope)?;
+            }
+        }
     }
 }
 
 fn bind_builtins(b: &mut HashMap<String, Elt>) {
     b.insert("def".to_string(), Elt::BuiltinFunction(Builtin::Def));
     b.insert("quote".to_string(), Elt::BuiltinFunction(Builtin::Quote));
+    b.insert(
+        "quasiquote".to_string(),
+        Elt::BuiltinFunction(Builtin::Quasiquote),
+    );
+    b.insert(
+        "unquote".to_string(),
+        Elt::BuiltinFunction(Builtin::Unquote),
+    );
+    b.insert(
+        "unquote-splicing".to_string(),
+        Elt::BuiltinFunction(Builtin::UnquoteSplicing),
+    );
     b.insert("fn".to_string(), Elt::BuiltinFunction(Builtin::Fn_));
+    b.insert("let".to_string(), Elt::BuiltinFunction(Builtin::Let));
+    b.insert("do".to_string(), Elt::BuiltinFunction(Builtin::Do));
+    b.insert(
+        "macroexpand-1".to_string(),
+        Elt::BuiltinFunction(Builtin::MacroExpand1),
+    );
+    b.insert("gensym".to_string(), Elt::BuiltinFunction(Builtin::Gensym));
+    b.insert(
+        "hash-map".to_string(),
+        Elt::BuiltinFunction(Builtin::HashMap_),
+    );
+    b.insert("get".to_string(), Elt::BuiltinFunction(Builtin::Get));
+    b.insert("assoc".to_string(), Elt::BuiltinFunction(Builtin::Assoc));
+    b.insert("dissoc".to_string(), Elt::BuiltinFunction(Builtin::Dissoc));
+    b.insert("keys".to_string(), Elt::BuiltinFunction(Builtin::Keys));
+    b.insert("vals".to_string(), Elt::BuiltinFunction(Builtin::Vals));
+    b.insert(
+        "contains?".to_string(),
+        Elt::BuiltinFunction(Builtin::Contains_),
+    );
+    b.insert(
+        "hash-set".to_string(),
+        Elt::BuiltinFunction(Builtin::HashSet_),
+    );
+    b.insert("conj".to_string(), Elt::BuiltinFunction(Builtin::Conj));
+    b.insert("disj".to_string(), Elt::BuiltinFunction(Builtin::Disj));
+    b.insert("union".to_string(), Elt::BuiltinFunction(Builtin::Union));
+    b.insert(
+        "intersection".to_string(),
+        Elt::BuiltinFunction(Builtin::Intersection),
+    );
+    b.insert(
+        "difference".to_string(),
+        Elt::BuiltinFunction(Builtin::Difference),
+    );
+    b.insert(
+        "macroexpand".to_string(),
+        Elt::BuiltinFunction(Builtin::MacroExpand),
+    );
     b.insert("macro".to_string(), Elt::BuiltinFunction(Builtin::Macro));
     b.insert("car".to_string(), Elt::BuiltinFunction(Builtin::Car));
     b.insert("cdr".to_string(), Elt::BuiltinFunction(Builtin::Cdr));
     b.insert("cons".to_string(), Elt::BuiltinFunction(Builtin::Cons));
     b.insert("empty?".to_string(), Elt::BuiltinFunction(Builtin::Empty_));
+    b.insert("map".to_string(), Elt::BuiltinFunction(Builtin::Map));
+    b.insert("filter".to_string(), Elt::BuiltinFunction(Builtin::Filter));
+    b.insert("foldl".to_string(), Elt::BuiltinFunction(Builtin::Foldl));
+    b.insert(
+        "reverse".to_string(),
+        Elt::BuiltinFunction(Builtin::Reverse),
+    );
+    b.insert("range".to_string(), Elt::BuiltinFunction(Builtin::Range));
+    b.insert("list?".to_string(), Elt::BuiltinFunction(Builtin::List_));
     b.insert("if".to_string(), Elt::BuiltinFunction(Builtin::If));
     b.insert("not".to_string(), Elt::BuiltinFunction(Builtin::Not));
     b.insert("nth".to_string(), Elt::BuiltinFunction(Builtin::Nth));
+    b.insert("count".to_string(), Elt::BuiltinFunction(Builtin::Count));
+    b.insert("str".to_string(), Elt::BuiltinFunction(Builtin::Str));
+    b.insert("str/cat".to_string(), Elt::BuiltinFunction(Builtin::StrCat));
+    b.insert(
+        "str/substr".to_string(),
+        Elt::BuiltinFunction(Builtin::StrSubstr),
+    );
+    b.insert(
+        "str/contains".to_string(),
+        Elt::BuiltinFunction(Builtin::StrContains),
+    );
+    b.insert(
+        "str/split".to_string(),
+        Elt::BuiltinFunction(Builtin::StrSplit),
+    );
+    b.insert(
+        "str/join".to_string(),
+        Elt::BuiltinFunction(Builtin::StrJoin),
+    );
+    b.insert(
+        "str/trim".to_string(),
+        Elt::BuiltinFunction(Builtin::StrTrim),
+    );
+    b.insert(
+        "str/upper".to_string(),
+        Elt::BuiltinFunction(Builtin::StrUpper),
+    );
+    b.insert(
+        "str/lower".to_string(),
+        Elt::BuiltinFunction(Builtin::StrLower),
+    );
+    b.insert(
+        "str/starts-with?".to_string(),
+        Elt::BuiltinFunction(Builtin::StrStartsWith),
+    );
+    b.insert(
+        "str/ends-with?".to_string(),
+        Elt::BuiltinFunction(Builtin::StrEndsWith),
+    );
+    b.insert(
+        "str/replace".to_string(),
+        Elt::BuiltinFunction(Builtin::StrReplace),
+    );
+    b.insert(
+        "str/index-of".to_string(),
+        Elt::BuiltinFunction(Builtin::StrIndexOf),
+    );
+    b.insert(
+        "io/slurp".to_string(),
+        Elt::BuiltinFunction(Builtin::IoSlurp),
+    );
+    b.insert("io/spit".to_string(), Elt::BuiltinFunction(Builtin::IoSpit));
+    b.insert(
+        "io/append".to_string(),
+        Elt::BuiltinFunction(Builtin::IoAppend),
+    );
+    b.insert(
+        "io/lines".to_string(),
+        Elt::BuiltinFunction(Builtin::IoLines),
+    );
+    b.insert(
+        "io/list-dir".to_string(),
+        Elt::BuiltinFunction(Builtin::IoListDir),
+    );
+    b.insert(
+        "io/exists?".to_string(),
+        Elt::BuiltinFunction(Builtin::IoExists),
+    );
+    b.insert(
+        "io/delete".to_string(),
+        Elt::BuiltinFunction(Builtin::IoDelete),
+    );
+    b.insert(
+        "parallel-map".to_string(),
+        Elt::BuiltinFunction(Builtin::ParallelMap),
+    );
+    b.insert(
+        "parallel-filter".to_string(),
+        Elt::BuiltinFunction(Builtin::ParallelFilter),
+    );
+    b.insert(
+        "numerator".to_string(),
+        Elt::BuiltinFunction(Builtin::Numerator),
+    );
+    b.insert(
+        "denominator".to_string(),
+        Elt::BuiltinFunction(Builtin::Denominator),
+    );
+    b.insert("quot".to_string(), Elt::BuiltinFunction(Builtin::Quot));
+    b.insert("rem".to_string(), Elt::BuiltinFunction(Builtin::Rem));
+    b.insert("mod".to_string(), Elt::BuiltinFunction(Builtin::Mod));
+    b.insert("char".to_string(), Elt::BuiltinFunction(Builtin::Char));
+    b.insert("byte".to_string(), Elt::BuiltinFunction(Builtin::Byte));
+    b.insert("int".to_string(), Elt::BuiltinFunction(Builtin::Int));
     b.insert("+".to_string(), Elt::BuiltinFunction(Builtin::Plus));
     b.insert("-".to_string(), Elt::BuiltinFunction(Builtin::Minus));
     b.insert("*".to_string(), Elt::BuiltinFunction(Builtin::Mult));
     b.insert("/".to_string(), Elt::BuiltinFunction(Builtin::Div));
     b.insert("=".to_string(), Elt::BuiltinFunction(Builtin::Equal));
+    b.insert("==".to_string(), Elt::BuiltinFunction(Builtin::NumEq));
+    b.insert("<".to_string(), Elt::BuiltinFunction(Builtin::LT));
     b.insert(">".to_string(), Elt::BuiltinFunction(Builtin::GT));
+    b.insert("<=".to_string(), Elt::BuiltinFunction(Builtin::LTEq));
+    b.insert(">=".to_string(), Elt::BuiltinFunction(Builtin::GTEq));
+    b.insert(
+        "compare".to_string(),
+        Elt::BuiltinFunction(Builtin::Compare),
+    );
     b.insert("assert".to_string(), Elt::BuiltinFunction(Builtin::Assert));
     b.insert(
         "assert-eq".to_string(),
@@ -603,10 +2346,20 @@ fn bind_builtins(b: &mut HashMap<String, Elt>) {
     );
 }
 
+/// Evaluates a top-level form, expanding all of the macro calls in it once beforehand.
+pub fn eval_toplevel(
+    value: &Elt,
+    runtime: &mut Runtime,
+    scope: &Arc<Scope>,
+) -> Result<Elt, String> {
+    let expanded = macroexpand_all(value, &HashSet::new(), runtime, scope)?;
+    eval(&expanded, runtime, scope)
+}
+
 pub fn execute(runtime: &mut Runtime, ast: Vec<Elt>) {
+    let scope = Arc::new(Scope::default());
     for node in ast {
-        let scope = runtime.root_scope.clone();
-        if let Err(e) = eval(&node, runtime, &scope) {
+        if let Err(e) = eval_toplevel(&node, runtime, &scope) {
             println!("error during evaluation: {}", e);
             break;
         }
@@ -614,9 +2367,10 @@ pub fn execute(runtime: &mut Runtime, ast: Vec<Elt>) {
 }
 
 pub fn new() -> Runtime {
-    let mut root_scope = Scope {
-        bindings: HashMap::new(),
-    };
+    let mut root_scope = Scope::default();
     bind_builtins(&mut root_scope.bindings);
-    Runtime { root_scope }
+    Runtime {
+        root_scope: Arc::new(root_scope),
+        in_worker: false,
+    }
 }
@@ -0,0 +1,330 @@
+use crate::model::{Elt, Runtime, Scope};
+use crate::{lex, parse, run_script, runtime, PRELUDE};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{env, fs, process};
+
+fn new_runtime() -> Runtime {
+    let mut runtime = runtime::new();
+    let prelude = parse::parse(&lex::lex(PRELUDE.to_string()).unwrap()).unwrap();
+    runtime::execute(&mut runtime, prelude);
+    runtime
+}
+
+/// Evaluates each form in `source`, returning the value of the last one.
+fn run(runtime: &mut Runtime, source: &str) -> Result<Elt, String> {
+    let ast = parse::parse(&lex::lex(source.to_string())?)?;
+    let scope = Arc::new(Scope::default());
+    let mut result = Elt::Nil;
+    for node in ast {
+        result = runtime::eval_toplevel(&node, runtime, &scope)?;
+    }
+    Ok(result)
+}
+
+/// The fastest of a few runs of `source`, to smooth out scheduling noise.
+fn time_best(runtime: &mut Runtime, source: &str) -> Duration {
+    (0..3)
+        .map(|_| {
+            let start = Instant::now();
+            run(runtime, source).unwrap();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+#[test]
+fn call_cost_does_not_grow_with_globals() {
+    let calls = "(defn countdown [n] (if (== n 0) 0 (countdown (- n 1)))) (countdown 5000)";
+
+    let mut few = new_runtime();
+    run(&mut few, "(def g 0)").unwrap();
+    let with_few = time_best(&mut few, calls);
+
+    let mut many = new_runtime();
+    let globals: String = (0..20_000).map(|i| format!("(def g{} {})", i, i)).collect();
+    run(&mut many, &globals).unwrap();
+    let with_many = time_best(&mut many, calls);
+
+    // copying the global environment per call made this thousands of times slower
+    assert!(
+        with_many < with_few * 4 + Duration::from_millis(20),
+        "5000 calls took {:?} with 20000 globals but {:?} with one",
+        with_many,
+        with_few
+    );
+}
+
+#[test]
+fn tail_forms_are_not_copied() {
+    let countdown = |data: &str| {
+        format!(
+            "(defn countdown [n] (if (== n 0) 0 (if false '({}) (countdown (- n 1))))) \
+             (countdown 5000)",
+            data
+        )
+    };
+    let mut runtime = new_runtime();
+    let with_small = time_best(&mut runtime, &countdown(""));
+    let data: Vec<String> = (0..2000).map(|i| i.to_string()).collect();
+    let with_large = time_best(&mut runtime, &countdown(&data.join(" ")));
+
+    // copying the chosen branch of an if on every iteration made this several times slower
+    assert!(
+        with_large < with_small * 2 + Duration::from_millis(20),
+        "5000 iterations took {:?} carrying a 2000-element list but {:?} without",
+        with_large,
+        with_small
+    );
+}
+
+#[test]
+fn map_updates_share_structure() {
+    let build = |n: usize| {
+        format!(
+            "(let [m (foldl (fn [m i] (assoc m i i)) {{}} (range {}))] \
+               (count (foldl (fn [m i] (dissoc m (* 2 i))) m (range {}))))",
+            n,
+            n / 2
+        )
+    };
+    let mut runtime = new_runtime();
+    assert_eq!(Ok(Elt::Int(10000)), run(&mut runtime, &build(20000)));
+    let with_small = time_best(&mut runtime, &build(2000));
+    let with_large = time_best(&mut runtime, &build(20000));
+
+    // copying the map on every assoc made ten times the entries take a hundred times as long
+    assert!(
+        with_large < with_small * 25 + Duration::from_millis(50),
+        "20000 entries took {:?} but 2000 took {:?}",
+        with_large,
+        with_small
+    );
+}
+
+#[test]
+fn set_updates_share_structure() {
+    let build = |n: usize| {
+        format!(
+            "(let [s (foldl conj #{{}} (range {}))] \
+               (count (difference (foldl disj s (range {})) #{{0 1 2}})))",
+            n,
+            n / 2
+        )
+    };
+    let mut runtime = new_runtime();
+    assert_eq!(Ok(Elt::Int(10000)), run(&mut runtime, &build(20000)));
+    let with_small = time_best(&mut runtime, &build(2000));
+    let with_large = time_best(&mut runtime, &build(20000));
+
+    // copying the set on every conj made ten times the elements take a hundred times as long
+    assert!(
+        with_large < with_small * 25 + Duration::from_millis(50),
+        "20000 elements took {:?} but 2000 took {:?}",
+        with_large,
+        with_small
+    );
+}
+
+#[test]
+fn self_recursion_runs_in_constant_stack() {
+    let mut runtime = new_runtime();
+    let result = run(
+        &mut runtime,
+        "((fn [f] (f f 20000)) (fn [self n] (if (= n 0) 'done (self self (- n 1)))))",
+    );
+    assert_eq!(Ok(Elt::Symbol("done".to_string())), result);
+}
+
+#[test]
+fn sequence_builtins_handle_long_lists() {
+    let mut runtime = new_runtime();
+    assert_eq!(
+        Ok(Elt::Int(100000)),
+        run(&mut runtime, "(count (range 100000))")
+    );
+    assert_eq!(
+        Ok(Elt::Int(4999950000)),
+        run(&mut runtime, "(foldl + 0 (range 100000))")
+    );
+    assert_eq!(
+        Ok(Elt::Int(50000)),
+        run(
+            &mut runtime,
+            "(count (filter #(= 0 (mod % 4)) (map #(* 2 %) (range 100000))))"
+        )
+    );
+    assert_eq!(
+        Ok(Elt::Int(99999)),
+        run(&mut runtime, "(car (reverse (range 100000)))")
+    );
+}
+
+/// A fresh directory under the system temp dir, removed again by the caller.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("halftau-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir(&dir).unwrap();
+    dir
+}
+
+fn string(s: &str) -> Elt {
+    Elt::String_(s.to_string())
+}
+
+#[test]
+fn io_builtins_round_trip_files() {
+    let dir = scratch_dir("io");
+    let file = dir.join("notes.txt");
+    let mut runtime = new_runtime();
+    run(
+        &mut runtime,
+        &format!(
+            "(def dir {:?}) (def file {:?})",
+            dir.to_str().unwrap(),
+            file.to_str().unwrap()
+        ),
+    )
+    .unwrap();
+
+    assert_eq!(Ok(Elt::Bool(false)), run(&mut runtime, "(io/exists? file)"));
+    assert_eq!(Ok(Elt::Nil), run(&mut runtime, "(io/spit file \"one\\n\")"));
+    assert_eq!(
+        Ok(Elt::Nil),
+        run(&mut runtime, "(io/append file \"two\\n\")")
+    );
+    assert_eq!(
+        Ok(string("one\ntwo\n")),
+        run(&mut runtime, "(io/slurp file)")
+    );
+    assert_eq!(
+        Ok(Elt::List(vec![string("one"), string("two")])),
+        run(&mut runtime, "(io/lines file)")
+    );
+    assert_eq!(Ok(Elt::Nil), run(&mut runtime, "(io/spit file \"three\")"));
+    assert_eq!(Ok(string("three")), run(&mut runtime, "(io/slurp file)"));
+
+    assert_eq!(Ok(Elt::Bool(true)), run(&mut runtime, "(io/exists? file)"));
+    assert_eq!(
+        Ok(Elt::List(vec![string("notes.txt")])),
+        run(&mut runtime, "(io/list-dir dir)")
+    );
+
+    assert_eq!(Ok(Elt::Nil), run(&mut runtime, "(io/delete file)"));
+    assert_eq!(Ok(Elt::Bool(false)), run(&mut runtime, "(io/exists? file)"));
+    assert_eq!(
+        Ok(Elt::List(vec![])),
+        run(&mut runtime, "(io/list-dir dir)")
+    );
+    assert_eq!(Ok(Elt::Nil), run(&mut runtime, "(io/delete dir)"));
+    assert!(!dir.exists());
+}
+
+#[test]
+fn io_errors_name_the_builtin_and_path() {
+    let dir = scratch_dir("io-errors");
+    let missing = dir.join("missing.txt");
+    let missing = missing.to_str().unwrap();
+    let mut runtime = new_runtime();
+
+    assert_eq!(
+        Err(format!(
+            "io/slurp failed for {:?}: No such file or directory (os error 2)",
+            missing
+        )),
+        run(&mut runtime, &format!("(io/slurp {:?})", missing))
+    );
+    assert_eq!(
+        Err(format!(
+            "io/delete failed for {:?}: No such file or directory (os error 2)",
+            missing
+        )),
+        run(&mut runtime, &format!("(io/delete {:?})", missing))
+    );
+    assert_eq!(
+        Err(format!(
+            "Could not read {}: No such file or directory (os error 2)",
+            missing
+        )),
+        run_script(&mut runtime, missing)
+    );
+    assert_eq!(
+        Err(format!(
+            "Could not read {}: Is a directory (os error 21)",
+            dir.to_str().unwrap()
+        )),
+        run_script(&mut runtime, dir.to_str().unwrap())
+    );
+
+    fs::remove_dir(&dir).unwrap();
+}
+
+#[test]
+fn arithmetic_errors_name_the_builtin() {
+    let mut runtime = new_runtime();
+    for (source, error) in &[
+        ("(/ 1 0)", "division by zero"),
+        ("(/ 1 2 0)", "division by zero"),
+        ("(numerator)", "numerator takes one parameter; 0 found"),
+        (
+            "(denominator 1 2)",
+            "denominator takes one parameter; 2 found",
+        ),
+        ("(quot 1)", "quot takes two parameters; 1 found"),
+        ("(rem 1)", "rem takes two parameters; 1 found"),
+        ("(mod 1 2 3)", "mod takes two parameters; 3 found"),
+    ] {
+        assert_eq!(
+            Err(error.to_string()),
+            run(&mut runtime, source),
+            "{}",
+            source
+        );
+    }
+}
+
+fn list(elts: &[i64]) -> Elt {
+    Elt::List(elts.iter().copied().map(Elt::Int).collect())
+}
+
+#[test]
+fn parallel_map_and_filter_keep_element_order() {
+    let mut runtime = new_runtime();
+    assert_eq!(
+        Ok(list(&[2, 3, 4])),
+        run(&mut runtime, "(parallel-map #(+ % 1) '(1 2 3))")
+    );
+    assert_eq!(
+        Ok(list(&[1, 4, 9, 16, 25, 36, 49, 64, 81])),
+        run(&mut runtime, "(parallel-map #(* % %) (range 1 10))")
+    );
+    assert_eq!(
+        Ok(list(&[0, 2, 4, 6, 8])),
+        run(
+            &mut runtime,
+            "(parallel-filter #(== 0 (mod % 2)) (range 10))"
+        )
+    );
+    assert_eq!(Ok(list(&[])), run(&mut runtime, "(parallel-map id '())"));
+    assert_eq!(
+        run(&mut runtime, "(map #(* 3 %) (range 100))"),
+        run(&mut runtime, "(parallel-map #(* 3 %) (range 100))")
+    );
+}
+
+#[test]
+fn parallel_workers_see_but_cannot_define_globals() {
+    let mut runtime = new_runtime();
+    run(&mut runtime, "(def offset 10)").unwrap();
+    assert_eq!(
+        Ok(list(&[11, 12])),
+        run(&mut runtime, "(parallel-map #(+ offset %) '(1 2))")
+    );
+    assert_eq!(
+        Err("parallel-map failed on 1: def cannot be used inside a parallel worker".to_string()),
+        run(&mut runtime, "(parallel-map (fn [x] (def leaked x)) '(1))")
+    );
+    assert!(run(&mut runtime, "leaked").is_err());
+}